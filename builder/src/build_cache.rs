@@ -0,0 +1,162 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Serialize};
+
+use crate::ir::IR;
+
+/// File the cache is stored in, relative to the source root.
+const CACHE_FILE: &str = "build-cache";
+
+/// Results of previous builds that are reused as long as their inputs don't
+/// change.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct BuildCache {
+    /// Identifies the builder binary that produced the cache.
+    ///
+    /// Cached pages are discarded when the builder changes.
+    builder: u64,
+
+    /// File the cache was loaded from and is saved to, none for caches that
+    /// only live in memory.
+    #[serde(skip)]
+    file: Option<PathBuf>,
+
+    /// Timestamps at which files were added to git by their path.
+    pub created: HashMap<PathBuf, u64>,
+
+    /// Previous page builds by the path of their build procedure.
+    pages: HashMap<String, CachedPage>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedPage {
+    /// Inputs the page was built from and their fingerprints at that time.
+    dependencies: BTreeMap<String, Option<u64>>,
+    html: String,
+}
+
+impl BuildCache {
+    /// Read the cache of the last build of the sources in [root] or create an
+    /// empty one.
+    pub fn load(root: &Path) -> Self {
+        let builder = builder_fingerprint();
+        let file = root.join(CACHE_FILE);
+        let cache: Option<BuildCache> = fs::read_to_string(&file).ok()
+            .and_then(|txt| serde_yml::from_str(&txt).ok());
+        match cache {
+            Some(mut cache) => {
+                if cache.builder != builder {
+                    cache.builder = builder;
+                    cache.pages.clear();
+                }
+                cache.file = Some(file);
+                cache
+            },
+            None => BuildCache {
+                builder,
+                file: Some(file),
+                ..BuildCache::default()
+            },
+        }
+    }
+
+    /// Create an empty cache that is never read from or saved to disk.
+    pub fn in_memory() -> Self {
+        BuildCache {
+            builder: builder_fingerprint(),
+            ..BuildCache::default()
+        }
+    }
+
+    pub fn save(&self) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let saved = serde_yml::to_string(self).ok()
+            .and_then(|txt| fs::write(file, txt).ok());
+        if saved.is_none() {
+            const YELLOW: &str = "\x1b[33m";
+            const CLEAR: &str = "\x1b[0m";
+            eprintln!("{YELLOW}WARNING{CLEAR}: Unable to save build cache, the next build will start from scratch.");
+        }
+    }
+
+    /// Get the html of a page built from the procedure at [path] if none of its
+    /// inputs changed since.
+    pub fn page(&self, path: &str, data: &IR) -> Option<String> {
+        let page = self.pages.get(path)?;
+        if page.dependencies.iter().all(|(dep, fingerprint)| &data.fingerprint(dep) == fingerprint) {
+            Some(page.html.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Remember the [html] that was built from [dependencies].
+    pub fn insert_page(&mut self, path: String, dependencies: Dependencies, data: &IR, html: String) {
+        let dependencies = dependencies.0.into_inner().into_iter()
+            .map(|dep| {
+                let fingerprint = data.fingerprint(&dep);
+                (dep, fingerprint)
+            })
+            .collect();
+        self.pages.insert(path, CachedPage { dependencies, html });
+    }
+
+    /// Inputs the page built from the procedure at [path] was built from.
+    pub fn dependencies(&self, path: &str) -> Vec<String> {
+        self.pages.get(path)
+            .map(|page| page.dependencies.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Forget all pages that were not built from one of the procedures at
+    /// [paths].
+    pub fn retain_pages(&mut self, paths: &[String]) {
+        self.pages.retain(|path, _| paths.contains(path));
+    }
+}
+
+/// Inputs accessed while building a page.
+///
+/// Entries are paths relative to the source root without file extensions
+/// for components (e.g. `templates/base-page.html`, `components/footer`,
+/// `pages/blog/index.yml`).
+#[derive(Debug, Default)]
+pub struct Dependencies(RefCell<BTreeSet<String>>);
+
+impl Dependencies {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&self, dependency: String) {
+        self.0.borrow_mut().insert(dependency);
+    }
+
+    /// Add all entries of [other].
+    pub fn extend(&self, other: &Dependencies) {
+        self.0.borrow_mut().extend(other.0.borrow().iter().cloned());
+    }
+}
+
+/// Hash of a value that identifies it between builds.
+pub fn fingerprint<T: Hash + ?Sized>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn builder_fingerprint() -> u64 {
+    let modified = std::env::current_exe().ok()
+        .and_then(|exe| fs::metadata(exe).ok())
+        .and_then(|meta| meta.modified().ok())
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|modified| modified.as_millis());
+    fingerprint(&(env!("CARGO_PKG_VERSION"), modified))
+}
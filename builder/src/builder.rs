@@ -1,14 +1,41 @@
+use std::cell::{OnceCell, RefCell};
 use std::cmp::PartialEq;
 use std::collections::HashMap;
+use std::path::Path;
 use itertools::Itertools;
+use pulldown_cmark::{Event, Tag, TagEnd};
+use serde::Deserialize;
 
+use crate::build_cache::Dependencies;
 use crate::fs_tree::ParsedFsEntry;
-use crate::ir::{FwHTML, FwHTMLResolveError, IR};
+use crate::html;
+use crate::html::Context;
+use crate::ir;
+use crate::ir::{FwHTML, FwHTMLError, FwHTMLResolveError, VarType, IR};
+
+/// Name of the step that sets the global variables no step of a page sets.
+const GLOBALS_STEP: &str = "~~ globals";
+
+/// Built-in variables that differ between pages, set in [GLOBALS_STEP].
+pub const PAGE_VARIABLES: [&str; 2] = ["canonical-url", "page-path"];
+
+/// File with the defaults of the pages in its directory and subdirectories.
+pub const DEFAULTS_FILE: &str = "_defaults.yml";
 
 #[derive(Debug, Clone)]
 pub struct BuildProcedure {
-    template: String,
+    /// None for partials that leave the template to the procedures including
+    /// them.
+    template: Option<String>,
     steps: Vec<Step>,
+    /// Names of validation rules that don't apply to the built page.
+    pub suppressed: Vec<String>,
+    /// Paths relative to the pages dir of the procedures to merge into this
+    /// one, in order.
+    includes: Vec<String>,
+    /// Paths relative to the pages dir of all procedures that were merged
+    /// into this one, and of defaults files that would be if they existed.
+    included: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -17,7 +44,7 @@ pub struct Step {
     vars: HashMap<String, Value>,
 }
 
-#[derive(PartialEq, Debug, Clone)]
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub enum Value {
     Text(String),
     Int(u64),
@@ -27,6 +54,8 @@ pub enum Value {
     Md {
         path: String,
     },
+    /// Markdown text, e.g. the body of a markdown page.
+    MdText(String),
     TextFile {
         path: String,
     },
@@ -35,6 +64,95 @@ pub enum Value {
         /// Name of a *component*.
         item_template: String,
     },
+    /// Items for `{{ for }}` blocks.
+    List(Vec<Value>),
+    /// Trusted html that is inserted without escaping.
+    Raw(String),
+}
+
+/// Variables of `website.yml` that every page can use, by their name.
+#[derive(Debug, Default, Deserialize)]
+#[serde(transparent)]
+pub struct GlobalVariables(HashMap<String, loader::Value>);
+
+impl GlobalVariables {
+    pub fn load(self) -> Result<HashMap<String, Value>, BuildProcedureLoadError> {
+        self.0.into_iter()
+            .map(|(key, val)| Value::load(val, &key).map(|val| (key, val)))
+            .collect()
+    }
+}
+
+/// Values generated from files and directories, shared by all pages of a
+/// build together with the dependencies they were generated from.
+#[derive(Debug, Default)]
+pub struct ValueCache(RefCell<HashMap<Value, (Generated, Dependencies)>>);
+
+impl ValueCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the cached output of [value] or generate it, adding the
+    /// dependencies of the value to [dependencies] either way.
+    fn get_or_generate(&self, value: &Value, dependencies: &Dependencies, generate: impl FnOnce(&Dependencies) -> Result<Generated, ValueGenerationError>) -> Result<Generated, ValueGenerationError> {
+        if let Some((generated, value_dependencies)) = self.0.borrow().get(value) {
+            dependencies.extend(value_dependencies);
+            return Ok(generated.clone());
+        }
+        let value_dependencies = Dependencies::new();
+        let generated = generate(&value_dependencies)?;
+        dependencies.extend(&value_dependencies);
+        self.0.borrow_mut().insert(value.clone(), (generated.clone(), value_dependencies));
+        Ok(generated)
+    }
+}
+
+/// Output of [Value::generate].
+#[derive(PartialEq, Debug, Clone)]
+pub enum Generated {
+    /// Plain text that gets escaped where it is inserted.
+    Text(String),
+    /// Markup that is inserted as is.
+    Html(String),
+    List(Vec<Generated>),
+}
+
+impl Generated {
+    /// Text to insert in place of the variable at [context], list items are
+    /// put on separate lines.
+    pub fn output(self, context: &Context) -> String {
+        match self {
+            Generated::Text(text) => {
+                let escaped = html::escape(&text, context);
+                ir::protect_literals(&escaped).unwrap_or(escaped)
+            },
+            Generated::Html(html) => html,
+            Generated::List(items) => items.into_iter()
+                .map(|item| item.output(context))
+                .join("\n"),
+        }
+    }
+
+    /// Whether `{{ if }}` blocks take their first branch.
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Generated::Text(text) | Generated::Html(text) => !text.trim().is_empty(),
+            Generated::List(items) => !items.is_empty(),
+        }
+    }
+
+    /// Transform the text of the value, or of each item of lists, without
+    /// changing whether it gets escaped.
+    pub fn map(self, transform: &impl Fn(String) -> String) -> Generated {
+        match self {
+            Generated::Text(text) => Generated::Text(transform(text)),
+            Generated::Html(html) => Generated::Html(transform(html)),
+            Generated::List(items) => Generated::List(items.into_iter()
+                .map(|item| item.map(transform))
+                .collect()),
+        }
+    }
 }
 
 impl BuildProcedure {
@@ -53,17 +171,7 @@ impl BuildProcedure {
                     name = Some(key);
                     continue
                 }
-                let val = match val {
-                    loader::Value::Tagged { inner } => match inner {
-                        loader::ValueTyped::UnixTimestamp { value } => Value::UnixTimestamp{ value },
-                        loader::ValueTyped::Md { path } => Value::Md{ path },
-                        loader::ValueTyped::Text { path } => Value::TextFile{ path },
-                        loader::ValueTyped::Index { path, item_template } => Value::Index{ path, item_template },
-                    }
-                    loader::Value::Text(txt) => Value::Text(txt),
-                    loader::Value::Int(val) => Value::Int(val),
-                    loader::Value::None => return Err(BuildProcedureLoadError::UnexpectNoneVariableValue),
-                };
+                let val = Value::load(val, &key)?;
                 vars.insert(key, val);
             }
             steps.push(Step { name, vars });
@@ -71,57 +179,340 @@ impl BuildProcedure {
         Ok(BuildProcedure {
             template: deserialized.template,
             steps,
+            suppressed: deserialized.suppress,
+            includes: deserialized.include,
+            included: Vec::new(),
         })
     }
 
-    pub fn execute(&self, data: &IR,) -> Result<String, BuildProcedureBuildError> {
-        let template: FwHTML = match data.templates.get(&self.template) {
-            None => return Err(BuildProcedureBuildError::TemplateNotFound(self.template.clone())),
+    /// Whether the procedure in the file [file_name] is only included by
+    /// others and not built as a page.
+    pub fn is_partial(file_name: &str) -> bool {
+        file_name.starts_with('_')
+    }
+
+    /// Load a markdown page that starts with front matter between two `---`
+    /// lines, or [None] if [md] has no front matter.
+    ///
+    /// Front matter keys are variables of a single step, except for
+    /// `template`, `include` and `suppress` which work like in yml files and
+    /// `component` which is included as `content`. The markdown after the
+    /// front matter is the `text` variable.
+    pub fn from_markdown(md: &str) -> Option<Result<BuildProcedure, BuildProcedureLoadError>> {
+        let rest = md.strip_prefix("---\n").or_else(|| md.strip_prefix("---\r\n"))?;
+        let mut front_matter_end = 0;
+        for line in rest.split_inclusive('\n') {
+            if line.trim_end() == "---" {
+                let body = &rest[front_matter_end + line.len()..];
+                return Some(Self::load_front_matter(&rest[..front_matter_end], body));
+            }
+            front_matter_end += line.len();
+        }
+        None
+    }
+
+    fn load_front_matter(front_matter: &str, body: &str) -> Result<BuildProcedure, BuildProcedureLoadError> {
+        let front_matter = match front_matter.trim().is_empty() {
+            true => "{}",
+            false => front_matter,
+        };
+        let deserialized: loader::FrontMatter = match serde_yml::from_str(front_matter) {
+            Err(err) => return Err(BuildProcedureLoadError::FormatError(err)),
+            Ok(v) => v,
+        };
+
+        let mut vars = HashMap::new();
+        for (key, val) in deserialized.vars {
+            let val = Value::load(val, &key)?;
+            vars.insert(key, val);
+        }
+        if let Some(component) = deserialized.component {
+            vars.insert(String::from("content"), Value::Text(format!("{{{{ components/{component} }}}}")));
+        }
+        vars.insert(String::from("text"), Value::MdText(body.to_string()));
+        Ok(BuildProcedure {
+            template: deserialized.template,
+            steps: vec![Step { name: None, vars }],
+            suppressed: deserialized.suppress,
+            includes: deserialized.include,
+            included: Vec::new(),
+        })
+    }
+
+    /// Paths relative to the pages dir of the procedures this one includes.
+    pub fn includes(&self) -> &[String] {
+        &self.includes
+    }
+
+    /// This procedure with the [defaults] and [included] procedures, that are
+    /// merged themselves, merged in before it in order.
+    ///
+    /// [defaults] are the paths of the defaults files of the directories
+    /// around the procedure, outermost first, with their procedure if the file
+    /// exists. [included] are the procedures of [BuildProcedure::includes].
+    ///
+    /// Later templates and steps override earlier ones, a step with the name
+    /// of an earlier step sets its variables in that step instead.
+    pub fn merged(&self, defaults: &[(String, Option<&BuildProcedure>)], included: &[&BuildProcedure]) -> BuildProcedure {
+        let mut merged = BuildProcedure {
+            template: None,
+            steps: Vec::new(),
+            suppressed: Vec::new(),
+            includes: Vec::new(),
+            included: Vec::new(),
+        };
+        let included = self.includes.iter()
+            .zip(included.iter().copied().map(Some));
+        for (path, procedure) in defaults.iter().map(|(path, procedure)| (path, *procedure)).chain(included) {
+            merged.included.push(path.clone());
+            let Some(procedure) = procedure else {
+                continue;
+            };
+            merged.included.extend(procedure.included.iter().cloned());
+            merged.merge(procedure);
+        }
+        merged.merge(self);
+        merged.included = merged.included.into_iter().unique().collect();
+        merged
+    }
+
+    fn merge(&mut self, other: &BuildProcedure) {
+        if other.template.is_some() {
+            self.template = other.template.clone();
+        }
+        for step in &other.steps {
+            let earlier = self.steps.iter_mut()
+                .find(|earlier| step.name.is_some() && earlier.name == step.name);
+            match earlier {
+                Some(earlier) => earlier.vars.extend(step.vars.clone()),
+                None => self.steps.push(step.clone()),
+            }
+        }
+        for rule in &other.suppressed {
+            if !self.suppressed.contains(rule) {
+                self.suppressed.push(rule.clone());
+            }
+        }
+    }
+
+    /// Build the page described by this procedure at the output path [page].
+    ///
+    /// All inputs that are accessed get added to [dependencies].
+    pub fn execute(&self, data: &IR, page: &str, values: &ValueCache, dependencies: &Dependencies) -> Result<String, BuildProcedureBuildError> {
+        let Some(template) = &self.template else {
+            return Err(BuildProcedureBuildError::NoTemplate);
+        };
+        dependencies.add(format!("templates/{template}"));
+        let template: FwHTML = match data.templates.get(template) {
+            None => return Err(BuildProcedureBuildError::TemplateNotFound(template.clone())),
             Some(template) => template.clone(),
         };
-        self.execute_with_template_override(data, template)
+        self.execute_with_template_override(data, page, template, values, dependencies)
     }
 
-    pub fn execute_with_template_override(&self, data: &IR, mut template: FwHTML) -> Result<String, BuildProcedureBuildError> {
+    /// Build the page with [template] instead of the template of the procedure.
+    ///
+    /// Every variable is generated at most once, when it is first used.
+    pub fn execute_with_template_override(&self, data: &IR, page: &str, mut template: FwHTML, values: &ValueCache, dependencies: &Dependencies) -> Result<String, BuildProcedureBuildError> {
+        for included in &self.included {
+            dependencies.add(format!("pages/{included}"));
+        }
+        let globals = self.globals(data, page);
+        let defaults = self.check_contract(data, &template, &globals)?;
+        let steps = self.steps.iter().chain(Some(&globals)).chain(&defaults).collect::<Vec<_>>();
+        let generated: Vec<HashMap<&String, OnceCell<Generated>>> = steps.iter()
+            .map(|step| step.vars.keys().map(|name| (name, OnceCell::new())).collect())
+            .collect();
         let mut vars = HashMap::new();
-        for step in &self.steps {
+        for (step, generated) in steps.iter().zip(&generated) {
+            let is_global = step.name.as_deref() == Some(GLOBALS_STEP);
             let mut step_vars = step.vars.iter()
-                .map(|(k, v)| (k, || v.generate(&data)));
+                .map(|(k, v)| {
+                    let cell = &generated[k];
+                    (k, move || {
+                        if is_global {
+                            dependencies.add(format!("globals/{k}"));
+                        }
+                        match cell.get() {
+                            Some(value) => Ok(value.clone()),
+                            None => v.generate(data, values, dependencies)
+                                .map(|value| cell.get_or_init(|| value).clone()),
+                        }
+                    })
+                });
             vars.extend(&mut step_vars);
 
-            template = match template.resolved(&data.components, &vars) {
-                Err(err) => return Err(BuildProcedureBuildError::TemplateResolveError(err)),
+            template = match template.resolved(&data.components, &vars, dependencies) {
+                Err(err) => return Err(BuildProcedureBuildError::TemplateResolveError(step.name.clone(), err)),
                 Ok(t) => t,
             };
         }
 
+        let last_step = self.steps.last().and_then(|step| step.name.clone());
+        template = match template.finalized(&vars) {
+            Err(err) => return Err(BuildProcedureBuildError::TemplateResolveError(last_step, err)),
+            Ok(t) => t,
+        };
+
         Ok(template.output())
     }
+
+    /// Step with the global variables of [data] and the variables of the
+    /// [page] at that output path that no step of the procedure sets.
+    fn globals(&self, data: &IR, page: &str) -> Step {
+        let page_path = format!("/{page}");
+        let mut vars = data.globals.clone();
+        vars.insert(String::from("canonical-url"), Value::Text(format!("{}{page_path}", data.config.site_url())));
+        vars.insert(String::from("page-path"), Value::Text(page_path));
+        vars.retain(|name, _| !self.steps.iter().any(|step| step.vars.contains_key(name)));
+        Step { name: Some(String::from(GLOBALS_STEP)), vars }
+    }
+
+    /// Make sure the steps or [globals] set every variable [template], and
+    /// the components it or the values of the steps include, declare. Values
+    /// of the steps must be of the declared type.
+    ///
+    /// Returns a step with the defaults of optional variables no step sets.
+    fn check_contract(&self, data: &IR, template: &FwHTML, globals: &Step) -> Result<Option<Step>, BuildProcedureBuildError> {
+        let mut contract = template.contract(&data.components);
+        for value in self.steps.iter().flat_map(|step| step.vars.values()) {
+            value.add_contract(data, &mut contract);
+        }
+
+        let last_step = self.steps.last().and_then(|step| step.name.clone());
+        let mut defaults = HashMap::new();
+        for declaration in contract {
+            let mut is_set = globals.vars.contains_key(&declaration.name);
+            for step in &self.steps {
+                if let Some(value) = step.vars.get(&declaration.name) {
+                    if !value.has_type(declaration.var_type) {
+                        return Err(BuildProcedureBuildError::MismatchedVarType(step.name.clone(), declaration.name, declaration.var_type));
+                    }
+                    is_set = true;
+                }
+            }
+            match declaration.default {
+                _ if is_set => {},
+                None => return Err(BuildProcedureBuildError::CantResolveVars(last_step, declaration.name)),
+                Some(default) => {
+                    let default = match declaration.var_type {
+                        _ if default.is_empty() => Value::Text(default),
+                        VarType::Text => Value::Text(default),
+                        VarType::Html => Value::Raw(default),
+                        VarType::Timestamp => Value::UnixTimestamp { value: default.parse().expect("checked when parsed") },
+                    };
+                    defaults.entry(declaration.name).or_insert(default);
+                },
+            }
+        }
+        Ok((!defaults.is_empty()).then(|| Step {
+            name: Some(String::from("~~ defaults")),
+            vars: defaults,
+        }))
+    }
 }
 
 #[derive(Debug)]
 pub enum BuildProcedureLoadError {
     FormatError(serde_yml::modules::error::Error),
-    UnexpectNoneVariableValue,
+    /// Name of the variable without value.
+    UnexpectNoneVariableValue(String),
+    /// Variable name, err
+    InvalidText(String, FwHTMLError),
 }
 
 #[derive(Debug)]
 pub enum BuildProcedureBuildError {
+    /// Neither the procedure nor the procedures it includes set a template.
+    NoTemplate,
     /// Which template was not found.
     TemplateNotFound(String),
     /// Which build step couldn't resolve which variable.
     CantResolveVars(Option<String>, String),
-    TemplateResolveError(FwHTMLResolveError),
+    /// Which build step sets which variable to a value that isn't of the
+    /// declared type.
+    MismatchedVarType(Option<String>, String, VarType),
+    /// In which build step resolving failed.
+    TemplateResolveError(Option<String>, FwHTMLResolveError),
 }
 
 impl Value {
-    /// Turn the variable into a html compatible string.
+    /// Convert a deserialized value of the variable [key].
+    fn load(value: loader::Value, key: &str) -> Result<Value, BuildProcedureLoadError> {
+        Ok(match value {
+            loader::Value::Tagged { inner } => match inner {
+                loader::ValueTyped::UnixTimestamp { value } => Value::UnixTimestamp{ value },
+                loader::ValueTyped::Md { path } => Value::Md{ path },
+                loader::ValueTyped::Text { path } => Value::TextFile{ path },
+                loader::ValueTyped::Index { path, item_template } => Value::Index{ path, item_template },
+                loader::ValueTyped::Raw { value } => Value::Raw(value),
+            }
+            loader::Value::Text(txt) => match ir::protect_literals(&txt).and_then(|txt| ir::check_tags(&txt)) {
+                Err(err) => return Err(BuildProcedureLoadError::InvalidText(key.to_string(), err)),
+                Ok(()) => Value::Text(txt),
+            },
+            loader::Value::Int(val) => Value::Int(val),
+            loader::Value::List(items) => Value::List(items.into_iter()
+                .map(|item| Value::load(item, key))
+                .collect::<Result<_, _>>()?),
+            loader::Value::None => return Err(BuildProcedureLoadError::UnexpectNoneVariableValue(key.to_string())),
+        })
+    }
+
+    /// Whether the value, or every item of lists, can be used for a variable
+    /// declared as [var_type].
+    ///
+    /// Text with tags can be anything once the tags are resolved.
+    fn has_type(&self, var_type: VarType) -> bool {
+        match (self, var_type) {
+            (Value::List(items), var_type) => items.iter().all(|item| item.has_type(var_type)),
+            (Value::Text(txt), _) if txt.contains("{{") => true,
+            (_, VarType::Html) => true,
+            (Value::Text(_) | Value::Int(_), VarType::Text) => true,
+            (Value::UnixTimestamp { .. } | Value::Int(_), VarType::Timestamp) => true,
+            _ => false,
+        }
+    }
+
+    /// Add the contracts of the components text values include to
+    /// [contract].
+    fn add_contract(&self, data: &IR, contract: &mut Vec<ir::Declaration>) {
+        match self {
+            Value::List(items) => items.iter().for_each(|item| item.add_contract(data, contract)),
+            Value::Text(txt) => if let Ok(html) = ir::protect_literals(txt).and_then(FwHTML::new) {
+                contract.extend(html.contract(&data.components));
+            },
+            _ => {},
+        }
+    }
+
+    /// Turn the variable into a html compatible string, or one per item for
+    /// lists.
+    ///
+    /// If the variable uses a path that isn't available resolving fails. Files
+    /// and components that are read get added to [dependencies].
     ///
-    /// If the variable uses a path that isn't available resolving fails.
-    pub fn generate(&self, data: &IR) -> Result<String, ValueGenerationError> {
+    /// Values read from files and directories are taken from [values] when
+    /// another page already generated them.
+    pub fn generate(&self, data: &IR, values: &ValueCache, dependencies: &Dependencies) -> Result<Generated, ValueGenerationError> {
         match self {
-            Value::Text(txt) => Ok(txt.clone()),
-            Value::Int(val) => Ok(val.to_string()),
+            Value::List(items) => items.iter()
+                .map(|item| item.generate(data, values, dependencies))
+                .collect::<Result<_, _>>()
+                .map(Generated::List),
+            Value::Text(txt) => Ok(Generated::Text(txt.clone())),
+            Value::Int(val) => Ok(Generated::Text(val.to_string())),
+            Value::Md { .. } | Value::MdText(_) | Value::TextFile { .. } | Value::Index { .. } => values.get_or_generate(self, dependencies, |dependencies| {
+                self.generate_html(data, values, dependencies).map(Generated::Html)
+            }),
+            _ => self.generate_html(data, values, dependencies).map(Generated::Html),
+        }
+    }
+
+    fn generate_html(&self, data: &IR, values: &ValueCache, dependencies: &Dependencies) -> Result<String, ValueGenerationError> {
+        match self {
+            Value::List(_) | Value::Text(_) | Value::Int(_) => unreachable!("not html"),
+            Value::Raw(html) => Ok(html.clone()),
             Value::UnixTimestamp { value } => {
                 let timestamp = match chrono::DateTime::from_timestamp(*value as i64, 0) {
                     None => return Err(ValueGenerationError::UnixTimestampOutOfReach),
@@ -132,17 +523,17 @@ impl Value {
                 let pretty = timestamp.format("%Y-%m-%d").to_string();
                 Ok(format!("<time datetime=\"{formal}\">{pretty}</time>").to_string())
             },
+            Value::MdText(md) => Ok(markdown(md)),
             Value::Md { path } => {
-                if let Some(ParsedFsEntry::TextFile(md)) = data.pages.get(&format!("pages/{path}").to_string()) {
-                    let parser = pulldown_cmark::Parser::new(&md);
-                    let mut html = String::new();
-                    pulldown_cmark::html::push_html(&mut html, parser);
-                    Ok(html)
-                } else {
-                    Err(ValueGenerationError::FileDoesntExist(path.clone()))
+                dependencies.add(format!("pages/{path}"));
+                match data.pages.get(&format!("pages/{path}").to_string()) {
+                    Some(ParsedFsEntry::TextFile(md)) => Ok(markdown(&md)),
+                    Some(ParsedFsEntry::BuildProcedure(_)) => Err(ValueGenerationError::MdIsPage(path.clone())),
+                    _ => Err(ValueGenerationError::FileDoesntExist(path.clone())),
                 }
             }
             Value::TextFile { path } => {
+                dependencies.add(format!("pages/{path}"));
                 if let Some(ParsedFsEntry::TextFile(txt)) = data.pages.get(&format!("pages/{path}").to_string()) {
                     Ok(txt)
                 } else {
@@ -150,6 +541,8 @@ impl Value {
                 }
             }
             Value::Index { path, item_template } => {
+                dependencies.add(format!("pages/{path}"));
+                dependencies.add(format!("components/{item_template}"));
                 let dir = match data.pages.get(&format!("pages/{}", &path).to_string()) {
                     None => return Err(ValueGenerationError::NoDirAtIndexPath(path.clone())),
                     Some(dir) => dir,
@@ -162,7 +555,7 @@ impl Value {
                     for child in children {
                         let child = child.clone();
                         if let ParsedFsEntry::BuildProcedure(mut proc) = child.content {
-                            if child.name == String::from("index.yml") {
+                            if child.name == "index.yml" || child.name == "index.md" || BuildProcedure::is_partial(&child.name) {
                                 continue;
                             }
                             if child.created.is_none() {
@@ -172,14 +565,16 @@ impl Value {
                                 None => return Err(ValueGenerationError::MissingComponent(item_template.clone())),
                                 Some(t) => t,
                             };
-                            let out_name = child.name.replace(".yml", ".html");
+                            let out_name = Path::new(&child.name).with_extension("html").to_str().unwrap().to_string();
+                            let page = format!("{path}/{out_name}");
                             proc.steps.insert(0, Step {
                                 name: Some(String::from("~~ index vars")),
                                 vars: HashMap::from([(String::from("link"), Value::Text(out_name))])
                             });
-                            let element_html = match proc.execute_with_template_override(data, template.clone()) {
+                            dependencies.add(format!("pages/{path}/{}", child.name));
+                            let element_html = match proc.execute_with_template_override(data, &page, template.clone(), values, dependencies) {
                                 Ok(html) => html,
-                                Err(err) => return Err(ValueGenerationError::CantBuildIndexItem(child.name.clone(), err)),
+                                Err(err) => return Err(ValueGenerationError::CantBuildIndexItem(format!("{path}/{}", child.name), err)),
                             };
                             // FIXME: component not found as template -> make template string
                             html += format!("\n{}", element_html).as_str();
@@ -194,11 +589,36 @@ impl Value {
     }
 }
 
+/// Render markdown to html, code is kept as literal text that isn't resolved.
+fn markdown(md: &str) -> String {
+    let mut in_code_block = false;
+    let parser = pulldown_cmark::Parser::new(md).map(|event| match event {
+        Event::Start(Tag::CodeBlock(_)) => {
+            in_code_block = true;
+            event
+        },
+        Event::End(TagEnd::CodeBlock) => {
+            in_code_block = false;
+            event
+        },
+        Event::Text(text) if in_code_block => Event::Html(ir::literal_braces(&html::escape(&text, &Context::Text)).into()),
+        Event::Code(text) => Event::Html(format!("<code>{}</code>", ir::literal_braces(&html::escape(&text, &Context::Text))).into()),
+        event => event,
+    });
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, parser);
+    html
+}
+
 #[derive(Debug)]
 pub enum ValueGenerationError {
     FileDoesntExist(String),
+    /// Path of a markdown file with front matter, which is built as a page
+    /// instead of being a value.
+    MdIsPage(String),
     UnixTimestampOutOfReach,
     NoDirAtIndexPath(String),
+    /// Path of the item relative to the pages dir.
     CantBuildIndexItem(String, BuildProcedureBuildError),
     MissingComponent(String),
     IndexGitTimestampMissing(String)
@@ -211,12 +631,32 @@ mod loader {
 
     #[derive(Deserialize, Debug)]
     pub(super) struct BuildFile {
-        pub template: String,
-        pub steps: Vec<BuildStep>
+        pub template: Option<String>,
+        #[serde(default)]
+        pub include: Vec<String>,
+        #[serde(default)]
+        pub steps: Vec<BuildStep>,
+        #[serde(default)]
+        pub suppress: Vec<String>,
     }
 
     pub(super) type BuildStep = HashMap<String, Value>;
 
+    /// Header of a markdown page.
+    #[derive(Deserialize, Debug)]
+    pub(super) struct FrontMatter {
+        pub template: Option<String>,
+        #[serde(default)]
+        pub include: Vec<String>,
+        #[serde(default)]
+        pub suppress: Vec<String>,
+        /// Component that shows the page as `content`.
+        pub component: Option<String>,
+        /// All other keys.
+        #[serde(flatten)]
+        pub vars: HashMap<String, Value>,
+    }
+
     #[derive(Debug, Deserialize, PartialEq)]
     #[serde(untagged, rename_all = "camelCase")]
     pub(super) enum Value {
@@ -226,6 +666,7 @@ mod loader {
         },
         Text(String),
         Int(u64),
+        List(Vec<Value>),
         None,
     }
 
@@ -248,9 +689,12 @@ mod loader {
             #[serde(rename="itemTemplate")]
             item_template: String,
         },
+        Raw {
+            value: String,
+        },
     }
 
-
+    #[cfg(test)]
     mod tests {
         use crate::builder::loader::BuildFile;
 
@@ -295,9 +739,44 @@ mod loader {
     }
 }
 
+#[cfg(test)]
 mod tests {
+    use std::cell::Cell;
     use std::collections::HashMap;
-    use crate::builder::{BuildProcedure, Value};
+
+    use std::fs;
+    use std::path::PathBuf;
+
+    use crate::build_cache::Dependencies;
+    use crate::builder::{markdown, BuildProcedure, Generated, Value, ValueCache, ValueGenerationError};
+    use crate::fs_tree::FsTree;
+    use crate::ir::{VarType, IR};
+    use crate::source_dir::SourceDir;
+
+    /// Sources with the [pages] and [components] by their path, pages were
+    /// added to git in the given order.
+    fn source(name: &str, pages: &[(&str, &str)], components: &[(&str, &str)]) -> IR {
+        let root = std::env::temp_dir().join(name);
+        let mut created = HashMap::new();
+        for (i, (path, content)) in pages.iter().enumerate() {
+            let file = root.join("pages").join(path);
+            fs::create_dir_all(file.parent().unwrap()).unwrap();
+            fs::write(&file, content).unwrap();
+            created.insert(file, i as u64);
+        }
+        let pages = FsTree::load(&root.join("pages"), &mut created).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+        IR::new(SourceDir {
+            website_yml: String::from("url: example.com"),
+            templates: HashMap::new(),
+            components: components.iter().map(|(name, html)| (name.to_string(), html.to_string())).collect(),
+            layout_css: String::new(),
+            style_css: String::new(),
+            pages,
+            static_files: Vec::<(PathBuf, Vec<u8>)>::new(),
+            git_commit: None,
+        }).unwrap()
+    }
 
     #[test]
     fn decodes_sample_blog_template() {
@@ -318,11 +797,11 @@ steps:
 
 "#);
         let procedure = procedure.unwrap();
-        assert_eq!(procedure.template, "base-page.html");
+        assert_eq!(procedure.template, Some(String::from("base-page.html")));
         let steps = procedure.steps;
         assert_eq!(steps.len(), 2);
-        assert_eq!(steps.get(0).unwrap().name, Some(String::from("Setup blog template")));
-        assert_eq!(steps.get(0).unwrap().vars, HashMap::from([
+        assert_eq!(steps.first().unwrap().name, Some(String::from("Setup blog template")));
+        assert_eq!(steps.first().unwrap().vars, HashMap::from([
             (String::from("title"), Value::Text(String::from("How many lines of code are in Android?"))),
             (String::from("content"), Value::Text(String::from("{{ components/blog-entry }}"))),
         ]));
@@ -334,4 +813,156 @@ steps:
         ]));
 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn decodes_suppressions() {
+        let procedure = BuildProcedure::new(r#"template: base-page.html
+suppress:
+  - missing-index
+steps: []
+"#).unwrap();
+        assert_eq!(procedure.suppressed, vec![String::from("missing-index")]);
+        let procedure = BuildProcedure::new("template: base-page.html\nsteps: []\n").unwrap();
+        assert!(procedure.suppressed.is_empty());
+    }
+
+    #[test]
+    fn decodes_lists() {
+        let procedure = BuildProcedure::new(r#"template: base-page.html
+steps:
+  - tags:
+      - android
+      - type: md
+        path: tags/rust.md
+"#).unwrap();
+        assert_eq!(procedure.steps[0].vars, HashMap::from([
+            (String::from("tags"), Value::List(vec![
+                Value::Text(String::from("android")),
+                Value::Md{ path: String::from("tags/rust.md") },
+            ])),
+        ]));
+    }
+    #[test]
+    fn merges_included_procedures() {
+        let base = BuildProcedure::new(r#"template: base-page.html
+suppress:
+  - missing-index
+steps:
+  - "Setup":
+    content: "{{ components/blog-entry }}"
+    title: Blog
+"#).unwrap();
+        let post = BuildProcedure::new(r#"include:
+  - blog/_post.yml
+steps:
+  - "Setup":
+    title: Post
+  - text: Hello
+"#).unwrap();
+        assert_eq!(post.includes(), [String::from("blog/_post.yml")]);
+        let merged = post.merged(&[], &[&base]);
+        assert_eq!(merged.template, Some(String::from("base-page.html")));
+        assert_eq!(merged.suppressed, vec![String::from("missing-index")]);
+        assert_eq!(merged.included, vec![String::from("blog/_post.yml")]);
+        assert_eq!(merged.steps.len(), 2);
+        assert_eq!(merged.steps[0].vars, HashMap::from([
+            (String::from("content"), Value::Text(String::from("{{ components/blog-entry }}"))),
+            (String::from("title"), Value::Text(String::from("Post"))),
+        ]));
+        assert!(merged.includes().is_empty());
+        assert!(BuildProcedure::is_partial("_post.yml") && !BuildProcedure::is_partial("post.yml"));
+
+        let defaults = BuildProcedure::new(r#"template: blog-page.html
+steps:
+  - "Setup":
+    title: Untitled
+    author: derdilla
+"#).unwrap();
+        let merged = post.merged(&[(String::from("_defaults.yml"), None), (String::from("blog/_defaults.yml"), Some(&defaults))], &[&base]);
+        assert_eq!(merged.template, Some(String::from("base-page.html")));
+        assert_eq!(merged.included, vec![String::from("_defaults.yml"), String::from("blog/_defaults.yml"), String::from("blog/_post.yml")]);
+        assert_eq!(merged.steps[0].vars.len(), 3);
+        assert_eq!(merged.steps[0].vars["title"], Value::Text(String::from("Post")));
+    }
+
+    #[test]
+    fn decodes_markdown_pages() {
+        let procedure = BuildProcedure::from_markdown("---\ntemplate: base-page.html\ncomponent: blog-entry\ntitle: Hello\ntime:\n  type: unixTimestamp\n  value: 1704204000\n---\n# Hello\n").unwrap().unwrap();
+        assert_eq!(procedure.template, Some(String::from("base-page.html")));
+        assert_eq!(procedure.steps.len(), 1);
+        assert_eq!(procedure.steps[0].vars, HashMap::from([
+            (String::from("title"), Value::Text(String::from("Hello"))),
+            (String::from("time"), Value::UnixTimestamp{ value: 1704204000 }),
+            (String::from("content"), Value::Text(String::from("{{ components/blog-entry }}"))),
+            (String::from("text"), Value::MdText(String::from("# Hello\n"))),
+        ]));
+
+        let procedure = BuildProcedure::from_markdown("---\n---\nText").unwrap().unwrap();
+        assert_eq!(procedure.template, None);
+        assert_eq!(procedure.steps[0].vars, HashMap::from([(String::from("text"), Value::MdText(String::from("Text")))]));
+
+        assert!(BuildProcedure::from_markdown("# Hello\n---\n").is_none());
+        assert!(BuildProcedure::from_markdown("---\ntitle: Hello\n").is_none());
+        assert!(BuildProcedure::from_markdown("---\ntitle:\n---\n").unwrap().is_err());
+    }
+
+    #[test]
+    fn checks_value_types() {
+        let text = Value::Text(String::from("Title"));
+        assert!(text.has_type(VarType::Text) && text.has_type(VarType::Html) && !text.has_type(VarType::Timestamp));
+        assert!(Value::Text(String::from("{{ time }}")).has_type(VarType::Timestamp));
+        let md = Value::Md{ path: String::from("a.md") };
+        assert!(md.has_type(VarType::Html) && !md.has_type(VarType::Text));
+        let times = Value::List(vec![Value::UnixTimestamp{ value: 0 }, Value::Int(1)]);
+        assert!(times.has_type(VarType::Timestamp) && !times.has_type(VarType::Text));
+    }
+
+    #[test]
+    fn generates_shared_values_once() {
+        let values = ValueCache::new();
+        let value = Value::Md{ path: String::from("blog/a.md") };
+        let calls = Cell::new(0);
+        let generate = |dependencies: &Dependencies| {
+            calls.set(calls.get() + 1);
+            dependencies.add(String::from("pages/blog/a.md"));
+            Ok(Generated::Html(String::from("<p>a</p>")))
+        };
+        let (first, second) = (Dependencies::new(), Dependencies::new());
+        assert_eq!(values.get_or_generate(&value, &first, generate).unwrap(), Generated::Html(String::from("<p>a</p>")));
+        assert_eq!(values.get_or_generate(&value, &second, generate).unwrap(), Generated::Html(String::from("<p>a</p>")));
+        assert_eq!(calls.get(), 1);
+        assert_eq!(format!("{first:?}"), format!("{second:?}"));
+    }
+
+    #[test]
+    fn indexes_pages_except_index_and_partials() {
+        let data = source("builder-index-items", &[
+            ("blog/index.md", "---\ntitle: Blog\n---\n"),
+            ("blog/_post.yml", "steps:\n  - title: Post\n"),
+            ("blog/a.md", "---\ntitle: A\n---\nText"),
+            ("blog/b.yml", "steps:\n  - title: B\n"),
+        ], &[("item", "<li>{{ link }}</li>")]);
+        let index = Value::Index { path: String::from("blog"), item_template: String::from("item") };
+        let html = index.generate(&data, &ValueCache::new(), &Dependencies::new()).unwrap();
+        assert_eq!(html, Generated::Html(String::from("\n<li>b.html</li>\n<li>a.html</li>")));
+    }
+
+    #[test]
+    fn rejects_markdown_pages_as_values() {
+        let data = source("builder-md-values", &[
+            ("notes.md", "# Notes"),
+            ("post.md", "---\ntitle: Post\n---\n# Post"),
+        ], &[]);
+        let notes = Value::Md { path: String::from("notes.md") };
+        assert_eq!(notes.generate(&data, &ValueCache::new(), &Dependencies::new()).unwrap(), Generated::Html(String::from("<h1>Notes</h1>\n")));
+        let post = Value::Md { path: String::from("post.md") };
+        let err = post.generate(&data, &ValueCache::new(), &Dependencies::new()).unwrap_err();
+        assert!(matches!(err, ValueGenerationError::MdIsPage(path) if path == "post.md"));
+    }
+
+    #[test]
+    fn keeps_markdown_code_literal() {
+        let html = markdown("Use `${{ steps.x }}`:\n\n```yml\nif: ${{ a < b }}\n```\n\n{{ title }}\n");
+        assert_eq!(html, "<p>Use <code>$&#123;{ steps.x }}</code>:</p>\n<pre><code class=\"language-yml\">if: $&#123;{ a &lt; b }}\n</code></pre>\n<p>{{ title }}</p>\n");
+    }
+}
@@ -0,0 +1,677 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Range;
+
+use cssparser::{AtRuleParser, BasicParseErrorKind, CowRcStr, ParseError, ParseErrorKind, Parser, ParserInput, ParserState, QualifiedRuleParser, SourceLocation, StyleSheetParser, ToCss, Token};
+use html5ever::Namespace;
+use itertools::Itertools;
+use scraper::selector::CssString;
+use selectors::parser::{Combinator, Component, Selector, SelectorImpl, SelectorParseErrorKind};
+use selectors::visitor::SelectorVisitor;
+
+/// A stylesheet parsed into its top level rules.
+///
+/// Selectors are fully parsed, while declarations and at-rule preludes are
+/// kept as they appear in the source, without comments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stylesheet {
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rule {
+    /// `<selectors> { <declarations> }`
+    Style {
+        selectors: Vec<StyleSelector>,
+        declarations: String,
+    },
+    /// `@<name> <prelude>;` or `@<name> <prelude> { <block> }`
+    At {
+        name: String,
+        prelude: String,
+        block: Option<AtRuleBlock>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AtRuleBlock {
+    /// Nested rules of conditional group rules like `@media` or `@supports`.
+    Rules(Vec<Rule>),
+    /// Block content of all other at-rules (e.g. `@keyframes`, `@font-face`).
+    Raw(String),
+}
+
+/// A single selector of the selector list of a style rule.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StyleSelector {
+    /// The selector as written in the stylesheet.
+    pub source: String,
+    selector: Selector<SourceSelectors>,
+}
+
+/// Names of classes, tags and attributes present in a document.
+#[derive(Debug, Default)]
+pub struct UsedNames {
+    pub classes: HashSet<String>,
+    pub tags: HashSet<String>,
+    pub attributes: HashSet<String>,
+}
+
+impl Stylesheet {
+    pub fn parse(css: &str) -> Result<Self, StylesheetParseError> {
+        let tokens = Tokens::scan(css);
+        if let Some(comment) = tokens.comments.iter().find(|c| !is_terminated(&css[(*c).clone()])) {
+            return Err(StylesheetParseError::UnterminatedComment(comment.start));
+        }
+        if let Some(block) = tokens.unclosed_blocks.first() {
+            return Err(StylesheetParseError::UnbalancedBlock(*block));
+        }
+        let mut input = ParserInput::new(css);
+        Ok(Stylesheet {
+            rules: RuleParser { css }.parse_rules(&mut Parser::new(&mut input))?,
+        })
+    }
+
+    /// Create a copy of this stylesheet that only contains rules that can
+    /// apply to a document using [used] names.
+    ///
+    /// Rules are dropped conservatively: selectors inside functional
+    /// pseudo-classes (like `:is(...)` or `:has(...)`) and ids are not
+    /// considered.
+    pub fn tree_shaken(&self, used: &UsedNames) -> Self {
+        Stylesheet {
+            rules: shake_rules(&self.rules, used),
+        }
+    }
+
+    /// Serialize the stylesheet back into css.
+    pub fn output(&self) -> String {
+        output_rules(&self.rules)
+    }
+
+    /// Move `@charset` and `@import` rules in front of all other rules.
+    ///
+    /// Required when concatenating stylesheets, as those rules are ignored
+    /// when they aren't at the start of a stylesheet.
+    pub fn hoist_imports(&mut self) {
+        let (imports, others): (Vec<Rule>, Vec<Rule>) = self.rules.drain(..)
+            .partition(|r| matches!(r, Rule::At { name, .. } if name == "charset" || name == "import"));
+        self.rules = imports;
+        self.rules.extend(others);
+    }
+
+    pub fn append(&mut self, other: Stylesheet) {
+        self.rules.extend(other.rules);
+    }
+
+    /// Selectors of all style rules, including rules nested in at-rules.
+    pub fn selectors(&self) -> Vec<&StyleSelector> {
+        fn collect<'a>(rules: &'a [Rule], selectors: &mut Vec<&'a StyleSelector>) {
+            for rule in rules {
+                match rule {
+                    Rule::Style { selectors: list, .. } => selectors.extend(list),
+                    Rule::At { block: Some(AtRuleBlock::Rules(nested)), .. } => collect(nested, selectors),
+                    Rule::At { .. } => {},
+                }
+            }
+        }
+        let mut selectors = Vec::new();
+        collect(&self.rules, &mut selectors);
+        selectors
+    }
+}
+
+impl StyleSelector {
+    /// Names of all classes the selector mentions, including those in
+    /// functional pseudo-classes like `:not(...)` or `:has(...)`.
+    pub fn classes(&self) -> HashSet<String> {
+        struct Classes(HashSet<String>);
+
+        impl SelectorVisitor for Classes {
+            type Impl = SourceSelectors;
+
+            fn visit_simple_selector(&mut self, component: &Component<SourceSelectors>) -> bool {
+                match component {
+                    Component::Class(Ident(name)) => {
+                        self.0.insert(name.clone());
+                    },
+                    // Relative selectors of `:has(...)` aren't visited by the selector itself
+                    Component::Has(relative) => relative.iter().for_each(|r| { r.selector.visit(self); }),
+                    _ => {},
+                }
+                true
+            }
+        }
+
+        let mut classes = Classes(HashSet::new());
+        self.selector.visit(&mut classes);
+        classes.0
+    }
+
+    /// Rewrite the selector so it can be matched against a static document.
+    ///
+    /// Pseudo-elements and pseudo-classes that depend on user interaction are
+    /// removed, compounds that would become empty match any element instead.
+    pub fn static_selector(&self) -> String {
+        let mut combinators = self.selector.iter_raw_match_order().rev().filter_map(|c| c.as_combinator());
+        let compounds = self.selector.iter_raw_match_order().as_slice().split(|c| c.is_combinator()).rev();
+        let mut out = String::new();
+        let mut compound = String::new();
+        for components in compounds {
+            for component in components {
+                match component {
+                    Component::NonTSPseudoClass(pseudo_class) if pseudo_class.is_dynamic() => {},
+                    Component::PseudoElement(_) => {},
+                    component => component.to_css(&mut compound).expect("Writing to a string can't fail"),
+                }
+            }
+            let combinator = combinators.next();
+            // Pseudo-elements are part of the compound in front of them
+            if combinator == Some(Combinator::PseudoElement) {
+                continue;
+            }
+            out += if compound.is_empty() { "*" } else { &compound };
+            compound.clear();
+            if let Some(combinator) = combinator {
+                combinator.to_css(&mut out).expect("Writing to a string can't fail");
+            }
+        }
+        out
+    }
+}
+
+/// Pseudo-classes that depend on user interaction or browser state and never
+/// match in a static document.
+const DYNAMIC_PSEUDO_CLASSES: [&str; 10] = [
+    "hover", "active", "focus", "focus-within", "focus-visible", "visited", "link", "any-link", "target", "autofill",
+];
+
+/// At-rules whose block contains rules rather than declarations.
+const CONDITIONAL_GROUP_RULES: [&str; 5] = ["media", "supports", "document", "layer", "container"];
+
+#[derive(Debug)]
+pub enum StylesheetParseError {
+    /// Byte offset of the comment start.
+    UnterminatedComment(usize),
+    /// Byte offset of the block.
+    UnbalancedBlock(usize),
+    /// Byte offset of the rule.
+    MissingBlock(usize),
+    /// Byte offset of the selector, selector, err
+    InvalidSelector(usize, String, String),
+}
+
+/// Comments and nesting of the tokens of a stylesheet.
+struct Tokens {
+    /// Byte ranges of all comments, including unterminated ones.
+    comments: Vec<Range<usize>>,
+    /// Byte offsets of blocks that are still open at the end of the input.
+    unclosed_blocks: Vec<usize>,
+}
+
+impl Tokens {
+    fn scan(css: &str) -> Self {
+        fn walk(input: &mut Parser, tokens: &mut Tokens) {
+            loop {
+                let start = input.position().byte_index();
+                match input.next_including_whitespace_and_comments() {
+                    Err(_) => return,
+                    Ok(Token::Comment(_)) => tokens.comments.push(start..input.position().byte_index()),
+                    Ok(Token::CurlyBracketBlock | Token::SquareBracketBlock | Token::ParenthesisBlock | Token::Function(_)) => {
+                        let mut content_end = start;
+                        let _ = input.parse_nested_block(|nested| {
+                            walk(nested, tokens);
+                            content_end = nested.position().byte_index();
+                            Ok::<_, ParseError<()>>(())
+                        });
+                        // A closed block ends after its closing bracket
+                        if input.position().byte_index() == content_end {
+                            tokens.unclosed_blocks.push(start);
+                        }
+                    },
+                    Ok(_) => {},
+                }
+            }
+        }
+
+        let mut tokens = Tokens { comments: Vec::new(), unclosed_blocks: Vec::new() };
+        let mut input = ParserInput::new(css);
+        walk(&mut Parser::new(&mut input), &mut tokens);
+        tokens
+    }
+}
+
+fn is_terminated(comment: &str) -> bool {
+    comment.len() >= 4 && comment.ends_with("*/")
+}
+
+/// Remove all comments from [css].
+fn strip_comments(css: &str) -> String {
+    let mut out = String::with_capacity(css.len());
+    let mut last_end = 0;
+    for comment in Tokens::scan(css).comments {
+        out += &css[last_end..comment.start];
+        last_end = comment.end;
+    }
+    out += &css[last_end..];
+    out
+}
+
+/// Replace the content of comments with spaces, keeping the byte offsets and
+/// lines of everything else.
+///
+/// Unterminated comments extend to the end of [css].
+pub fn blank_comments(css: &str) -> String {
+    let mut out = String::with_capacity(css.len());
+    let mut last_end = 0;
+    for comment in Tokens::scan(css).comments {
+        out += &css[last_end..comment.start];
+        for c in css[comment.clone()].chars() {
+            match c {
+                '\n' => out.push(c),
+                c => out.extend(std::iter::repeat_n(' ', c.len_utf8())),
+            }
+        }
+        last_end = comment.end;
+    }
+    out += &css[last_end..];
+    out
+}
+
+/// Consume the rest of [input] and return its source without comments.
+fn remaining(input: &mut Parser) -> String {
+    let start = input.position();
+    while input.next().is_ok() {}
+    strip_comments(input.slice_from(start)).trim().to_string()
+}
+
+/// Parses the rules of a stylesheet and of the blocks of conditional group
+/// rules.
+struct RuleParser<'i> {
+    css: &'i str,
+}
+
+struct AtRulePrelude {
+    name: String,
+    prelude: String,
+}
+
+impl<'i> RuleParser<'i> {
+    fn parse_rules(&mut self, input: &mut Parser<'i, '_>) -> Result<Vec<Rule>, StylesheetParseError> {
+        let css = self.css;
+        let mut rules = Vec::new();
+        for rule in StyleSheetParser::new(input, self) {
+            match rule {
+                Ok(rule) => rules.push(rule),
+                Err((ParseError { kind: ParseErrorKind::Custom(err), .. }, _)) => return Err(err),
+                // Preludes of at-rules always parse, so only style rules can fail here
+                Err((_, source)) => return Err(StylesheetParseError::MissingBlock(offset_in(css, source))),
+            }
+        }
+        Ok(rules)
+    }
+}
+
+impl<'i> QualifiedRuleParser<'i> for RuleParser<'i> {
+    type Prelude = Vec<StyleSelector>;
+    type QualifiedRule = Rule;
+    type Error = StylesheetParseError;
+
+    fn parse_prelude<'t>(&mut self, input: &mut Parser<'i, 't>) -> Result<Self::Prelude, ParseError<'i, Self::Error>> {
+        input.parse_comma_separated(|input| {
+            let start = input.position();
+            let selector = Selector::parse(&SelectorParser, input)
+                .and_then(|selector| input.expect_exhausted().map(|_| selector).map_err(ParseError::from));
+            if selector.is_err() {
+                while input.next().is_ok() {}
+            }
+            let source = input.slice_from(start);
+            let offset = start.byte_index() + source.len() - source.trim_start().len();
+            let source = strip_comments(source).trim().to_string();
+            match selector {
+                Ok(selector) => Ok(StyleSelector { source, selector }),
+                Err(err) => {
+                    Err(input.new_custom_error(StylesheetParseError::InvalidSelector(offset, source, describe(err.kind))))
+                },
+            }
+        })
+    }
+
+    fn parse_block<'t>(&mut self, selectors: Self::Prelude, _: &ParserState, input: &mut Parser<'i, 't>) -> Result<Rule, ParseError<'i, Self::Error>> {
+        Ok(Rule::Style { selectors, declarations: remaining(input) })
+    }
+}
+
+impl<'i> AtRuleParser<'i> for RuleParser<'i> {
+    type Prelude = AtRulePrelude;
+    type AtRule = Rule;
+    type Error = StylesheetParseError;
+
+    fn parse_prelude<'t>(&mut self, name: CowRcStr<'i>, input: &mut Parser<'i, 't>) -> Result<Self::Prelude, ParseError<'i, Self::Error>> {
+        Ok(AtRulePrelude { name: name.to_string(), prelude: remaining(input) })
+    }
+
+    fn rule_without_block(&mut self, prelude: Self::Prelude, _: &ParserState) -> Result<Rule, ()> {
+        Ok(Rule::At { name: prelude.name, prelude: prelude.prelude, block: None })
+    }
+
+    fn parse_block<'t>(&mut self, prelude: Self::Prelude, _: &ParserState, input: &mut Parser<'i, 't>) -> Result<Rule, ParseError<'i, Self::Error>> {
+        let block = if CONDITIONAL_GROUP_RULES.contains(&prelude.name.to_ascii_lowercase().as_str()) {
+            AtRuleBlock::Rules(self.parse_rules(input).map_err(|err| input.new_custom_error(err))?)
+        } else {
+            AtRuleBlock::Raw(remaining(input))
+        };
+        Ok(Rule::At { name: prelude.name, prelude: prelude.prelude, block: Some(block) })
+    }
+}
+
+/// Describe why a selector can't be parsed.
+fn describe(err: ParseErrorKind<SelectorParseErrorKind>) -> String {
+    match err {
+        ParseErrorKind::Basic(BasicParseErrorKind::EndOfInput) => String::from("Selector ends unexpectedly"),
+        ParseErrorKind::Custom(SelectorParseErrorKind::EmptySelector) => String::from("Selector is empty"),
+        ParseErrorKind::Custom(SelectorParseErrorKind::DanglingCombinator) => String::from("Combinator is missing a selector"),
+        ParseErrorKind::Custom(SelectorParseErrorKind::UnexpectedIdent(ident)) => format!("Unexpected `{ident}`"),
+        ParseErrorKind::Basic(BasicParseErrorKind::UnexpectedToken(token))
+        | ParseErrorKind::Custom(SelectorParseErrorKind::ClassNeedsIdent(token)
+            | SelectorParseErrorKind::NoIdentForPseudo(token)
+            | SelectorParseErrorKind::PseudoElementExpectedIdent(token)
+            | SelectorParseErrorKind::BadValueInAttr(token)
+            | SelectorParseErrorKind::UnexpectedTokenInAttributeSelector(token)) => format!("Unexpected `{}`", token.to_css_string()),
+        ParseErrorKind::Basic(err) => err.to_string(),
+        ParseErrorKind::Custom(err) => format!("{err:?}"),
+    }
+}
+
+/// Byte offset of [part], which is a slice of [css].
+fn offset_in(css: &str, part: &str) -> usize {
+    part.as_ptr() as usize - css.as_ptr() as usize
+}
+
+/// Selectors as they are written in stylesheets.
+///
+/// Unlike [scraper::Selector], all pseudo-classes and pseudo-elements are
+/// accepted, so the ones a static document can't match can be removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SourceSelectors;
+
+impl SelectorImpl for SourceSelectors {
+    type ExtraMatchingData<'a> = ();
+    type AttrValue = CssString;
+    type Identifier = Ident;
+    type LocalName = Ident;
+    type NamespaceUrl = Namespace;
+    type NamespacePrefix = Ident;
+    type BorrowedNamespaceUrl = Namespace;
+    type BorrowedLocalName = Ident;
+    type NonTSPseudoClass = PseudoClass;
+    type PseudoElement = PseudoElement;
+}
+
+/// Class, tag and attribute names of selectors.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct Ident(String);
+
+impl From<&str> for Ident {
+    fn from(name: &str) -> Self {
+        Ident(name.to_string())
+    }
+}
+
+impl ToCss for Ident {
+    fn to_css<W: fmt::Write>(&self, dest: &mut W) -> fmt::Result {
+        cssparser::serialize_identifier(&self.0, dest)
+    }
+}
+
+/// Pseudo-class that isn't tree-structural, like `:hover` or `:lang(en)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PseudoClass {
+    name: String,
+    arguments: Option<String>,
+}
+
+impl PseudoClass {
+    fn is_dynamic(&self) -> bool {
+        self.arguments.is_none() && DYNAMIC_PSEUDO_CLASSES.contains(&self.name.as_str())
+    }
+}
+
+impl ToCss for PseudoClass {
+    fn to_css<W: fmt::Write>(&self, dest: &mut W) -> fmt::Result {
+        dest.write_char(':')?;
+        cssparser::serialize_identifier(&self.name, dest)?;
+        match &self.arguments {
+            Some(arguments) => write!(dest, "({arguments})"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl selectors::parser::NonTSPseudoClass for PseudoClass {
+    type Impl = SourceSelectors;
+
+    fn is_active_or_hover(&self) -> bool {
+        self.name == "active" || self.name == "hover"
+    }
+
+    fn is_user_action_state(&self) -> bool {
+        self.is_active_or_hover() || self.name.starts_with("focus")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PseudoElement {
+    name: String,
+    arguments: Option<String>,
+}
+
+impl ToCss for PseudoElement {
+    fn to_css<W: fmt::Write>(&self, dest: &mut W) -> fmt::Result {
+        dest.write_str("::")?;
+        cssparser::serialize_identifier(&self.name, dest)?;
+        match &self.arguments {
+            Some(arguments) => write!(dest, "({arguments})"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl selectors::parser::PseudoElement for PseudoElement {
+    type Impl = SourceSelectors;
+
+    fn accepts_state_pseudo_classes(&self) -> bool {
+        true
+    }
+}
+
+struct SelectorParser;
+
+impl<'i> selectors::parser::Parser<'i> for SelectorParser {
+    type Impl = SourceSelectors;
+    type Error = SelectorParseErrorKind<'i>;
+
+    fn parse_is_and_where(&self) -> bool {
+        true
+    }
+
+    fn parse_has(&self) -> bool {
+        true
+    }
+
+    fn parse_non_ts_pseudo_class(&self, _: SourceLocation, name: CowRcStr<'i>) -> Result<PseudoClass, ParseError<'i, Self::Error>> {
+        Ok(PseudoClass { name: name.to_ascii_lowercase(), arguments: None })
+    }
+
+    fn parse_non_ts_functional_pseudo_class<'t>(&self, name: CowRcStr<'i>, arguments: &mut Parser<'i, 't>) -> Result<PseudoClass, ParseError<'i, Self::Error>> {
+        Ok(PseudoClass { name: name.to_ascii_lowercase(), arguments: Some(remaining(arguments)) })
+    }
+
+    fn parse_pseudo_element(&self, _: SourceLocation, name: CowRcStr<'i>) -> Result<PseudoElement, ParseError<'i, Self::Error>> {
+        Ok(PseudoElement { name: name.to_ascii_lowercase(), arguments: None })
+    }
+
+    fn parse_functional_pseudo_element<'t>(&self, name: CowRcStr<'i>, arguments: &mut Parser<'i, 't>) -> Result<PseudoElement, ParseError<'i, Self::Error>> {
+        Ok(PseudoElement { name: name.to_ascii_lowercase(), arguments: Some(remaining(arguments)) })
+    }
+}
+
+fn shake_rules(rules: &[Rule], used: &UsedNames) -> Vec<Rule> {
+    rules.iter()
+        .filter_map(|rule| match rule {
+            Rule::Style { selectors, .. } => {
+                if selectors.iter().any(|s| Requirements::of(s).are_met(used)) {
+                    Some(rule.clone())
+                } else {
+                    None
+                }
+            },
+            Rule::At { name, prelude, block: Some(AtRuleBlock::Rules(nested)) } => {
+                let nested = shake_rules(nested, used);
+                if nested.is_empty() {
+                    None
+                } else {
+                    Some(Rule::At {
+                        name: name.clone(),
+                        prelude: prelude.clone(),
+                        block: Some(AtRuleBlock::Rules(nested)),
+                    })
+                }
+            },
+            Rule::At { .. } => Some(rule.clone()),
+        })
+        .collect()
+}
+
+fn output_rules(rules: &[Rule]) -> String {
+    rules.iter()
+        .map(|rule| match rule {
+            Rule::Style { selectors, declarations } =>
+                format!("{} {{ {declarations} }}", selectors.iter().map(|s| &s.source).join(", ")),
+            Rule::At { name, prelude, block: None } => format!("@{name} {prelude};"),
+            Rule::At { name, prelude, block: Some(AtRuleBlock::Raw(raw)) } => format!("@{name} {prelude} {{ {raw} }}"),
+            Rule::At { name, prelude, block: Some(AtRuleBlock::Rules(nested)) } =>
+                format!("@{name} {prelude} {{\n{}\n}}", output_rules(nested)),
+        })
+        .join("\n")
+}
+
+/// Names that must be present in a document for a selector to match.
+#[derive(Debug, Default, PartialEq)]
+struct Requirements {
+    classes: Vec<String>,
+    tags: Vec<String>,
+    attributes: Vec<String>,
+}
+
+impl Requirements {
+    /// Requirements of the compounds of [selector], selectors nested in
+    /// functional pseudo-classes don't add any.
+    fn of(selector: &StyleSelector) -> Self {
+        let mut requirements = Requirements::default();
+        for component in selector.selector.iter_raw_parse_order_from(0) {
+            match component {
+                Component::Class(Ident(name)) => requirements.classes.push(name.clone()),
+                Component::LocalName(name) => requirements.tags.push(name.lower_name.0.clone()),
+                Component::AttributeInNoNamespaceExists { local_name_lower: Ident(name), .. }
+                | Component::AttributeInNoNamespace { local_name: Ident(name), .. } => requirements.attributes.push(name.clone()),
+                Component::AttributeOther(attribute) => requirements.attributes.push(attribute.local_name_lower.0.clone()),
+                _ => {},
+            }
+        }
+        requirements
+    }
+
+    fn are_met(&self, used: &UsedNames) -> bool {
+        self.classes.iter().all(|c| used.classes.contains(c))
+            && self.tags.iter().all(|t| used.tags.contains(t))
+            && self.attributes.iter().all(|a| used.attributes.contains(a))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::css::{blank_comments, Requirements, StyleSelector, Stylesheet, StylesheetParseError, UsedNames};
+
+    fn selector(selector: &str) -> StyleSelector {
+        let sheet = Stylesheet::parse(&format!("{selector} {{}}")).unwrap();
+        sheet.selectors()[0].clone()
+    }
+
+    #[test]
+    fn parses_rules() {
+        let sheet = Stylesheet::parse(r#"@import url('https://example.com/font.css');
+/* comment { */
+main + footer {
+    width: 100vw;
+}
+@media (max-width: 100px) {
+    .a, /* .c, */ .b:has(> .c) { color: red; }
+}
+@keyframes shimmer { from { opacity: 0; } to { opacity: 1; } }
+.b::after { content: "}"; }"#).unwrap();
+        assert_eq!(sheet.output(), r#"@import url('https://example.com/font.css');
+main + footer { width: 100vw; }
+@media (max-width: 100px) {
+.a, .b:has(> .c) { color: red; }
+}
+@keyframes shimmer { from { opacity: 0; } to { opacity: 1; } }
+.b::after { content: "}"; }"#);
+        let sources: Vec<&str> = sheet.selectors().into_iter().map(|s| s.source.as_str()).collect();
+        assert_eq!(sources, vec!["main + footer", ".a", ".b:has(> .c)", ".b::after"]);
+    }
+
+    #[test]
+    fn rejects_unbalanced_blocks() {
+        assert!(matches!(Stylesheet::parse("main { color: red;"), Err(StylesheetParseError::UnbalancedBlock(5))));
+        assert!(matches!(Stylesheet::parse("a {}\n/* main { color: red; }"), Err(StylesheetParseError::UnterminatedComment(5))));
+        assert!(matches!(Stylesheet::parse("a {}\nmain"), Err(StylesheetParseError::MissingBlock(5))));
+        assert!(matches!(Stylesheet::parse("a, b > {}"), Err(StylesheetParseError::InvalidSelector(3, s, m)) if s == "b >" && m == "Combinator is missing a selector"));
+        assert!(matches!(Stylesheet::parse("a..b {}"), Err(StylesheetParseError::InvalidSelector(0, _, m)) if m == "Unexpected `.`"));
+    }
+
+    #[test]
+    fn extracts_requirements() {
+        let req = Requirements::of(&selector("header > :is(h1, h2):has(+ time)"));
+        assert_eq!(req.tags, vec![String::from("header")]);
+        assert!(req.classes.is_empty());
+
+        let req = Requirements::of(&selector(".blog > article a[href^=\"/\"]:visited"));
+        assert_eq!(req.classes, vec![String::from("blog")]);
+        assert_eq!(req.tags, vec![String::from("article"), String::from("a")]);
+        assert_eq!(req.attributes, vec![String::from("href")]);
+
+        let req = Requirements::of(&selector("*.layout-link-button::after"));
+        assert_eq!(req.classes, vec![String::from("layout-link-button")]);
+        assert!(req.tags.is_empty());
+    }
+
+    #[test]
+    fn tree_shakes_unused_rules() {
+        let sheet = Stylesheet::parse(r#"
+main, .unused { margin: 0; }
+aside { float: right; }
+@media print { aside { display: none; } }
+.blog p { margin: 0; }
+"#).unwrap();
+        let used = UsedNames {
+            classes: [String::from("blog")].into(),
+            tags: [String::from("main"), String::from("p")].into(),
+            attributes: Default::default(),
+        };
+        assert_eq!(sheet.tree_shaken(&used).output(), "main, .unused { margin: 0; }\n.blog p { margin: 0; }");
+    }
+
+    #[test]
+    fn prepares_selectors_for_static_documents() {
+        assert_eq!(selector("a:visited").static_selector(), "a");
+        assert_eq!(selector(".layout-link-button::after").static_selector(), ".layout-link-button");
+        assert_eq!(selector("::selection").static_selector(), "*");
+        assert_eq!(selector("nav :hover > p:first-child").static_selector(), "nav * > p:first-child");
+        assert_eq!(selector("header > :is(h1, h2):has(+ time)").static_selector(), "header > :is(h1, h2):has(+ time)");
+        assert_eq!(selector("input:checked").static_selector(), "input:checked");
+        assert_eq!(blank_comments("a {}\n/* .b\n*/ .c {}"), "a {}\n     \n   .c {}");
+        assert_eq!(selector(".blog > article a[href=\".x\"]:not(.a, .blog)").classes(), [String::from("blog"), String::from("a")].into());
+        assert_eq!(selector("main:has(> .a .b)").classes(), [String::from("a"), String::from("b")].into());
+    }
+}
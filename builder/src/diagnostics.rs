@@ -0,0 +1,705 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use itertools::Itertools;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::builder::{BuildProcedureBuildError, BuildProcedureLoadError, ValueGenerationError};
+use crate::css::StylesheetParseError;
+use crate::filters::{FilterError, FilterParseError};
+use crate::fs_tree::{FsTreeLoadError, ParsedFsTreeParseError};
+use crate::ir::{FwHTMLError, FwHTMLResolveError, SourceFormatError};
+use crate::source_dir::SourceLoadError;
+use crate::website_builder::{PackError, WebsiteBuildError, WriteError};
+
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const BLUE: &str = "\x1b[34m";
+const BOLD: &str = "\x1b[1m";
+const CLEAR: &str = "\x1b[0m";
+
+/// A problem with the sources or the built website that is reported to the
+/// user.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// Name of the validation rule that found the problem.
+    pub rule: Option<&'static str>,
+    /// Affected file relative to the source root.
+    pub file: Option<PathBuf>,
+    pub location: Option<Box<Location>>,
+    /// What was done when the problem occurred, from the outermost to the
+    /// innermost step.
+    pub context: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// Position of a span inside a file.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Location {
+    /// Starting at 1.
+    pub line: usize,
+    /// Starting at 1, counted in characters.
+    pub column: usize,
+    /// Length of the marked span in characters.
+    pub length: usize,
+    /// Content of the line at which the span starts.
+    pub source_line: String,
+}
+
+impl Diagnostic {
+    pub fn error(message: String) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            message,
+            rule: None,
+            file: None,
+            location: None,
+            context: Vec::new(),
+        }
+    }
+
+    pub fn in_file(mut self, file: impl Into<PathBuf>) -> Self {
+        self.file = Some(file.into());
+        self
+    }
+
+    pub fn with_context(mut self, context: Vec<String>) -> Self {
+        self.context = context;
+        self
+    }
+
+    /// Mark the span at a 1-based [line] and [column] of the file.
+    pub fn at(mut self, root: &Path, line: usize, column: usize, length: usize) -> Self {
+        if let Some(content) = self.read_file(root) {
+            self.location = Location::new(&content, line, column, length).map(Box::new);
+        }
+        self
+    }
+
+    /// Mark the span at a byte [offset] of the file.
+    pub fn at_offset(mut self, root: &Path, offset: usize, length: usize) -> Self {
+        if let Some(content) = self.read_file(root) {
+            self.location = Location::from_offset(&content, offset, length).map(Box::new);
+        }
+        self
+    }
+
+    /// Mark the last of [needles] that are searched for in order, each one
+    /// after the previous.
+    ///
+    /// Needles only match at word boundaries. When a needle can't be found,
+    /// the last needle that was found is marked instead.
+    pub fn locate(mut self, root: &Path, needles: &[&str]) -> Self {
+        let content = match self.read_file(root) {
+            None => return self,
+            Some(content) => content,
+        };
+        let mut start = 0;
+        let mut found = None;
+        for needle in needles {
+            match find_word(&content[start..], needle) {
+                None => break,
+                Some(offset) => {
+                    found = Some((start + offset, needle.chars().count()));
+                    start += offset + needle.len();
+                },
+            }
+        }
+        if let Some((offset, length)) = found {
+            self.location = Location::from_offset(&content, offset, length).map(Box::new);
+        }
+        self
+    }
+
+    fn read_file(&self, root: &Path) -> Option<String> {
+        self.file.as_ref().and_then(|file| fs::read_to_string(root.join(file)).ok())
+    }
+
+    /// Format the diagnostic for humans, optionally with terminal colors.
+    pub fn render(&self, color: bool) -> String {
+        let paint = |code: &'static str| if color { code } else { "" };
+        let (label, label_color) = match self.severity {
+            Severity::Error => ("error", RED),
+            Severity::Warning => ("warning", YELLOW),
+        };
+        let mut out = format!("{}{}{label}{}: {}{}", paint(BOLD), paint(label_color), paint(CLEAR), self.message, paint(CLEAR));
+
+        let gutter = self.location.as_ref()
+            .map_or(1, |l| l.line.to_string().len());
+        let pad = " ".repeat(gutter);
+        let blue = paint(BLUE);
+        let clear = paint(CLEAR);
+        if let Some(file) = &self.file {
+            out += &format!("\n{pad}{blue}-->{clear} {}", file.display());
+            if let Some(location) = &self.location {
+                out += &format!(":{}:{}", location.line, location.column);
+                let marker_offset = " ".repeat(location.column - 1);
+                let marker = "^".repeat(location.length.max(1));
+                out += &format!("\n{pad} {blue}|{clear}");
+                out += &format!("\n{blue}{} |{clear} {}", location.line, location.source_line);
+                out += &format!("\n{pad} {blue}|{clear} {marker_offset}{}{marker}{clear}", paint(label_color));
+            }
+        }
+        for context in &self.context {
+            out += &format!("\n{pad} {blue}={clear} {context}");
+        }
+        out
+    }
+}
+
+impl Location {
+    fn new(content: &str, line: usize, column: usize, length: usize) -> Option<Self> {
+        let index = line.checked_sub(1)?;
+        // Parsers report unexpected ends of files on the line after the last.
+        let source_line = content.lines().nth(index)
+            .or_else(|| (index == content.lines().count()).then_some(""))?;
+        let line_length = source_line.chars().count();
+        let column = column.clamp(1, line_length.max(1));
+        Some(Location {
+            line,
+            column,
+            length: length.min(line_length + 1 - column).max(1),
+            source_line: source_line.to_string(),
+        })
+    }
+
+    pub fn from_offset(content: &str, offset: usize, length: usize) -> Option<Self> {
+        let before = content.get(..offset)?;
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+        Self::new(content, line, column, length)
+    }
+}
+
+/// Part of the build that failed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    /// Reading and parsing the sources.
+    Load,
+    /// Building, packing and compiling pages.
+    Build,
+    /// Checking the built website.
+    Validation,
+    /// Writing the website to disk.
+    Write,
+}
+
+impl Stage {
+    /// Exit code of the process when this stage fails.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Stage::Load => 2,
+            Stage::Build => 3,
+            Stage::Validation => 4,
+            Stage::Write => 5,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Stage::Load => "load",
+            Stage::Build => "build",
+            Stage::Validation => "validation",
+            Stage::Write => "write",
+        }
+    }
+}
+
+/// How diagnostics get reported.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Colored text for terminals.
+    Human,
+    Json,
+    /// Static Analysis Results Interchange Format 2.1.0 as understood by
+    /// GitHub code scanning.
+    Sarif,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "human" => Some(Format::Human),
+            "json" => Some(Format::Json),
+            "sarif" => Some(Format::Sarif),
+            _ => None,
+        }
+    }
+}
+
+/// Print [diagnostics] in the requested [format].
+///
+/// Human-readable diagnostics go to stderr with the progress of the build,
+/// machine-readable ones to stdout, which nothing else writes to, or to
+/// [output] if given. [failed] is the stage that failed if any.
+/// Machine-readable formats are always printed, even without diagnostics.
+pub fn report(diagnostics: &[Diagnostic], failed: Option<Stage>, format: Format, output: Option<&Path>) {
+    let report = match format {
+        Format::Human => diagnostics.iter()
+            .map(|diagnostic| diagnostic.render(output.is_none()))
+            .join("\n"),
+        Format::Json => json(diagnostics, failed),
+        Format::Sarif => sarif(diagnostics, failed),
+    };
+    match (output, format) {
+        (Some(file), _) => if let Err(err) = fs::write(file, report + "\n") {
+            eprintln!("{RED}ERROR{CLEAR}: Unable to write diagnostics to {}: {err}", file.display());
+        },
+        (None, Format::Human) => if !report.is_empty() {
+            eprintln!("{report}");
+        },
+        (None, _) => println!("{report}"),
+    }
+}
+
+fn json(diagnostics: &[Diagnostic], failed: Option<Stage>) -> String {
+    json!({
+        "failedStage": failed.map(|stage| stage.name()),
+        "diagnostics": diagnostics,
+    }).to_string()
+}
+
+fn sarif(diagnostics: &[Diagnostic], failed: Option<Stage>) -> String {
+    let rule = failed.map_or("builder", |stage| stage.name());
+    let rules: Vec<_> = diagnostics.iter()
+        .map(|diagnostic| diagnostic.rule.unwrap_or(rule))
+        .unique()
+        .map(|id| json!({ "id": id }))
+        .collect();
+    let results: Vec<_> = diagnostics.iter()
+        .map(|diagnostic| {
+            let mut text = diagnostic.message.clone();
+            for context in &diagnostic.context {
+                text += &format!("\n{context}");
+            }
+            let locations: Vec<_> = diagnostic.file.iter()
+                .map(|file| {
+                    let mut physical = json!({
+                        "artifactLocation": {
+                            "uri": file.to_string_lossy().replace('\\', "/"),
+                            "uriBaseId": "%SRCROOT%",
+                        },
+                    });
+                    if let Some(location) = &diagnostic.location {
+                        physical["region"] = json!({
+                            "startLine": location.line,
+                            "startColumn": location.column,
+                            "endColumn": location.column + location.length,
+                        });
+                    }
+                    json!({ "physicalLocation": physical })
+                })
+                .collect();
+            json!({
+                "ruleId": diagnostic.rule.unwrap_or(rule),
+                "level": match diagnostic.severity {
+                    Severity::Error => "error",
+                    Severity::Warning => "warning",
+                },
+                "message": { "text": text },
+                "locations": locations,
+            })
+        })
+        .collect();
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "builder",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                },
+            },
+            "results": results,
+        }],
+    }).to_string()
+}
+
+/// Find [needle] in [haystack] where it isn't directly preceded or followed
+/// by an identifier character.
+pub fn find_word(haystack: &str, needle: &str) -> Option<usize> {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+    haystack.match_indices(needle)
+        .map(|(idx, _)| idx)
+        .find(|idx| {
+            let before = haystack[..*idx].chars().next_back();
+            let after = haystack[idx + needle.len()..].chars().next();
+            !(before.is_some_and(is_ident)
+                || after.is_some_and(is_ident) && needle.ends_with(is_ident))
+        })
+}
+
+pub fn source_load(err: SourceLoadError, root: &Path) -> Diagnostic {
+    match err {
+        SourceLoadError::MissingFile(f) => Diagnostic::error(format!("Couldn't read file: {f}")),
+        SourceLoadError::NoSuchDirectory(d) => Diagnostic::error(format!("No such directory: {d}")),
+        SourceLoadError::CollidingNames(file, other) => Diagnostic::error(format!("Name only differs in case from `{other}`"))
+            .in_file(file),
+        SourceLoadError::BadFsTree(err) => {
+            let (message, path) = match err {
+                FsTreeLoadError::CantReadPath(path, err) => (format!("Can't read path: {err}"), path),
+                FsTreeLoadError::UnparsableFilename(path) => (String::from("Can't parse filename"), path),
+                FsTreeLoadError::IsSymlink(path) => (String::from("Unexpected symlink"), path),
+            };
+            let path = path.strip_prefix(root).map(Path::to_path_buf).unwrap_or(path);
+            Diagnostic::error(message)
+                .in_file(path)
+                .with_context(vec![String::from("while loading the pages directory")])
+        },
+    }
+}
+
+pub fn source_format(err: SourceFormatError, root: &Path) -> Diagnostic {
+    match err {
+        SourceFormatError::InvalidYaml(file, err) => yaml(err, file, root),
+        SourceFormatError::InvalidTemplateHTML(file, err) => fw_html(err, file, root),
+        SourceFormatError::InvalidCSS(file, err) => stylesheet(err, file, root),
+        SourceFormatError::InvalidSelector(file, selector, message) => Diagnostic::error(format!("Invalid selector `{selector}`: {message}"))
+            .in_file(file)
+            .locate(root, &[&selector]),
+        SourceFormatError::MissingParentTemplate(file, parent) => Diagnostic::error(format!("Extended template `templates/{parent}` doesn't exist"))
+            .in_file(file)
+            .locate(root, &[&parent]),
+        SourceFormatError::CyclicTemplates(chain) => Diagnostic::error(format!("Templates extend each other: {}", chain.join(" → ")))
+            .in_file(&chain[0])
+            .locate(root, &["extends"]),
+        SourceFormatError::UnknownTemplateBlock(file, block) => Diagnostic::error(format!("Block `{block}` doesn't exist in the extended templates"))
+            .in_file(file)
+            .locate(root, &[&format!("block {block}")]),
+        SourceFormatError::InvalidComposedTemplate(file, err) => {
+            let message = match err {
+                FwHTMLError::DocumentContainsErrors(errors) => {
+                    let context = errors.iter()
+                        .map(|e| format!("at line {} of the composed document: {}", e.line, e.message))
+                        .collect();
+                    return Diagnostic::error(String::from("Filling the extended templates with its blocks produces invalid html"))
+                        .in_file(file)
+                        .with_context(context);
+                },
+                FwHTMLError::NotATemplate => String::from("The extended templates must start with `<!DOCTYPE html>`"),
+                err => return fw_html(err, file, root),
+            };
+            Diagnostic::error(message)
+                .in_file(file)
+        },
+        SourceFormatError::InvalidWebsiteVariable(err) => build_procedure_load(err, PathBuf::from("website.yml"), root),
+        SourceFormatError::InvalidFsTree(ParsedFsTreeParseError::InvalidBuildProcedure(path, err)) => build_procedure_load(err, path, root),
+        SourceFormatError::InvalidFsTree(ParsedFsTreeParseError::MissingInclude(path, include)) => Diagnostic::error(format!("Included build procedure `pages/{include}` doesn't exist"))
+            .in_file(path)
+            .locate(root, &["include", &include]),
+        SourceFormatError::InvalidFsTree(ParsedFsTreeParseError::CyclicIncludes(chain)) => Diagnostic::error(format!("Build procedures include each other: {}", chain.iter().map(|path| path.display()).join(" → ")))
+            .in_file(&chain[0])
+            .locate(root, &["include"]),
+        SourceFormatError::InvalidFsTree(ParsedFsTreeParseError::CollidingPages(page, other)) => Diagnostic::error(format!("Builds the same page as `{}`", other.display()))
+            .in_file(page),
+    }
+}
+
+/// Diagnose an error in the variables of the yml [file].
+fn build_procedure_load(err: BuildProcedureLoadError, file: PathBuf, root: &Path) -> Diagnostic {
+    match err {
+        BuildProcedureLoadError::FormatError(err) => yaml(err, file, root),
+        BuildProcedureLoadError::InvalidText(var, err) => {
+            let mut diagnostic = fw_html(err, file, root);
+            diagnostic.context.push(format!("in variable `{var}`"));
+            diagnostic
+        },
+        BuildProcedureLoadError::UnexpectNoneVariableValue(var) => Diagnostic::error(format!("Variable `{var}` has no value"))
+            .in_file(file)
+            .locate(root, &[&var]),
+    }
+}
+
+fn stylesheet(err: StylesheetParseError, file: impl Into<PathBuf>, root: &Path) -> Diagnostic {
+    let (message, offset, length) = match err {
+        StylesheetParseError::UnterminatedComment(offset) => (String::from("Unterminated comment"), offset, 1),
+        StylesheetParseError::UnbalancedBlock(offset) => (String::from("Block is never closed"), offset, 1),
+        StylesheetParseError::MissingBlock(offset) => (String::from("Rule has no block"), offset, 1),
+        StylesheetParseError::InvalidSelector(offset, selector, message) =>
+            (format!("Invalid selector `{selector}`: {message}"), offset, selector.chars().count()),
+    };
+    Diagnostic::error(format!("Can't parse stylesheet: {message}"))
+        .in_file(file)
+        .at_offset(root, offset, length)
+}
+
+fn yaml(err: serde_yml::Error, file: impl Into<PathBuf>, root: &Path) -> Diagnostic {
+    let diagnostic = Diagnostic::error(format!("Invalid yaml: {err}"))
+        .in_file(file);
+    match err.location() {
+        Some(location) => diagnostic.at(root, location.line(), location.column(), 1),
+        None => diagnostic,
+    }
+}
+
+fn fw_html(err: FwHTMLError, file: impl Into<PathBuf>, root: &Path) -> Diagnostic {
+    match err {
+        FwHTMLError::NotATemplate => Diagnostic::error(String::from("Templates must start with `<!DOCTYPE html>`"))
+            .in_file(file)
+            .at(root, 1, 1, usize::MAX),
+        FwHTMLError::DocumentContainsErrors(errors) => {
+            let (first, others) = match errors.split_first() {
+                None => return Diagnostic::error(String::from("Invalid html")).in_file(file),
+                Some(errors) => errors,
+            };
+            Diagnostic::error(format!("Invalid html: {}", first.message))
+                .in_file(file)
+                .at(root, first.line, 1, usize::MAX)
+                .with_context(others.iter()
+                    .map(|e| format!("also at line {}: {}", e.line, e.message))
+                    .collect())
+        },
+        FwHTMLError::ContentOutsideOfBlocks(content) => {
+            let excerpt = content.lines().next().unwrap_or_default();
+            Diagnostic::error(format!("Templates that extend another template can only contain `{{{{ block }}}}`s, found `{excerpt}`"))
+                .in_file(file)
+                .locate(root, &[excerpt])
+        },
+        FwHTMLError::InvalidBlock(tag, problem) | FwHTMLError::InvalidDeclaration(tag, problem) => Diagnostic::error(format!("`{tag}` {problem}"))
+            .in_file(file)
+            .locate(root, &[&tag]),
+        FwHTMLError::InvalidFilter(tag, err) => {
+            let message = match err {
+                FilterParseError::UnknownFilter(filter) => format!("Unknown filter `{filter}` in `{tag}`"),
+                FilterParseError::InvalidArgument(filter, expected) => format!("Filter `{filter}` in `{tag}` expects {expected}"),
+            };
+            Diagnostic::error(message)
+                .in_file(file)
+                .locate(root, &[&tag])
+        },
+    }
+}
+
+pub fn website_build(err: WebsiteBuildError, root: &Path) -> Diagnostic {
+    match err {
+        WebsiteBuildError::PageBuildFailed(page, err) => {
+            let context = vec![format!("while building page `{}`", page.display())];
+            build_procedure(err, page, root, context)
+        },
+    }
+}
+
+/// Diagnose an error in the build procedure at [file] after [context].
+fn build_procedure(err: BuildProcedureBuildError, file: PathBuf, root: &Path, mut context: Vec<String>) -> Diagnostic {
+    match err {
+        BuildProcedureBuildError::NoTemplate => Diagnostic::error(String::from("No template is set, add a `template:` key"))
+            .in_file(file)
+            .with_context(context),
+        BuildProcedureBuildError::TemplateNotFound(template) => Diagnostic::error(format!("Template `{template}` doesn't exist"))
+            .in_file(file)
+            .with_context(context)
+            .locate(root, &["template", &template]),
+        BuildProcedureBuildError::CantResolveVars(step, var) => {
+            let mut needles = step_needles(&step, &mut context);
+            needles.push(var.clone());
+            Diagnostic::error(format!("Variable `{var}` can't be resolved"))
+                .in_file(file)
+                .with_context(context)
+                .locate(root, &needles.iter().map(String::as_str).collect::<Vec<_>>())
+        },
+        BuildProcedureBuildError::MismatchedVarType(step, var, var_type) => {
+            let mut needles = step_needles(&step, &mut context);
+            needles.push(var.clone());
+            Diagnostic::error(format!("Variable `{var}` must be {}", var_type.name()))
+                .in_file(file)
+                .with_context(context)
+                .locate(root, &needles.iter().map(String::as_str).collect::<Vec<_>>())
+        },
+        BuildProcedureBuildError::TemplateResolveError(step, err) => {
+            let needles = step_needles(&step, &mut context);
+            fw_html_resolve(err, file, root, context, needles)
+        },
+    }
+}
+
+/// Needles to locate [step] in a build procedure. Also adds the step to the
+/// [context].
+fn step_needles(step: &Option<String>, context: &mut Vec<String>) -> Vec<String> {
+    match step {
+        Some(step) => {
+            context.push(format!("in step \"{step}\""));
+            vec![String::from("steps"), step.clone()]
+        },
+        None => {
+            context.push(String::from("in an unnamed step"));
+            vec![String::from("steps")]
+        },
+    }
+}
+
+fn fw_html_resolve(err: FwHTMLResolveError, file: PathBuf, root: &Path, mut context: Vec<String>, mut needles: Vec<String>) -> Diagnostic {
+    match err {
+        FwHTMLResolveError::MissingComponent(component) => {
+            let reference = format!("components/{component}");
+            let referenced_in_page = fs::read_to_string(root.join(&file))
+                .is_ok_and(|content| find_word(&content, &reference).is_some());
+            if !referenced_in_page {
+                context.push(String::from("referenced by the template or one of its components"));
+            }
+            needles.push(reference);
+            Diagnostic::error(format!("Component `{component}` doesn't exist"))
+                .in_file(file)
+                .with_context(context)
+                .locate(root, &needles.iter().map(String::as_str).collect::<Vec<_>>())
+        },
+        FwHTMLResolveError::UnknownComponentArgument(component, arg) => {
+            needles.push(arg.clone());
+            Diagnostic::error(format!("Component `{component}` has no variable `{arg}`"))
+                .in_file(file)
+                .with_context(context)
+                .locate(root, &needles.iter().map(String::as_str).collect::<Vec<_>>())
+        },
+        FwHTMLResolveError::UndefinedLoopSource(var) => {
+            needles.push(var.clone());
+            Diagnostic::error(format!("Variable `{var}` is iterated by a `{{{{ for }}}}` block but never defined"))
+                .in_file(file)
+                .with_context(context)
+                .locate(root, &needles.iter().map(String::as_str).collect::<Vec<_>>())
+        },
+        FwHTMLResolveError::NotAList(var) => {
+            needles.push(var.clone());
+            Diagnostic::error(format!("Variable `{var}` is iterated by a `{{{{ for }}}}` block but isn't a list"))
+                .in_file(file)
+                .with_context(context)
+                .locate(root, &needles.iter().map(String::as_str).collect::<Vec<_>>())
+        },
+        FwHTMLResolveError::FilterFailed(tag, err) => {
+            let message = match err {
+                FilterError::NotADate(value) => format!("Filter in `{tag}` can't read `{value}` as date"),
+            };
+            needles.push(tag);
+            Diagnostic::error(message)
+                .in_file(file)
+                .with_context(context)
+                .locate(root, &needles.iter().map(String::as_str).collect::<Vec<_>>())
+        },
+        FwHTMLResolveError::Cycle(chain) => {
+            needles.push(chain[0].clone());
+            Diagnostic::error(format!("`{}` includes itself: {}", chain[0], chain.join(" → ")))
+                .in_file(file)
+                .with_context(context)
+                .locate(root, &needles.iter().map(String::as_str).collect::<Vec<_>>())
+        },
+        FwHTMLResolveError::VariableError(var, err) => {
+            context.push(format!("while generating variable `{var}`"));
+            needles.push(var);
+            value_generation(*err, file, root, context, needles)
+        },
+        FwHTMLResolveError::GeneratesErrorInDocument(err) => {
+            let mut diagnostic = fw_html(err, file.clone(), root);
+            diagnostic.message = format!("Resolving variables produces an invalid document: {}", diagnostic.message);
+            context.append(&mut diagnostic.context);
+            diagnostic.with_context(context)
+        },
+    }
+}
+
+fn value_generation(err: ValueGenerationError, file: PathBuf, root: &Path, context: Vec<String>, mut needles: Vec<String>) -> Diagnostic {
+    let message = match err {
+        ValueGenerationError::FileDoesntExist(path) => {
+            needles.push(path.clone());
+            format!("File `pages/{path}` doesn't exist")
+        },
+        ValueGenerationError::MdIsPage(path) => {
+            needles.push(path.clone());
+            format!("`pages/{path}` has front matter and is built as a page, remove it to use the file as markdown value")
+        },
+        ValueGenerationError::UnixTimestampOutOfReach => String::from("Unix timestamp is out of range"),
+        ValueGenerationError::NoDirAtIndexPath(path) => {
+            needles.push(path.clone());
+            format!("`pages/{path}` is not a directory")
+        },
+        ValueGenerationError::MissingComponent(component) => {
+            needles.push(component.clone());
+            format!("Component `{component}` doesn't exist")
+        },
+        ValueGenerationError::IndexGitTimestampMissing(name) => format!("`{name}` has not been committed to git, so it can't be sorted into the index"),
+        ValueGenerationError::CantBuildIndexItem(item, err) => {
+            let mut context = context;
+            context.push(format!("while building index item `pages/{item}`"));
+            return build_procedure(err, PathBuf::from(format!("pages/{item}")), root, context);
+        },
+    };
+    Diagnostic::error(message)
+        .in_file(file)
+        .with_context(context)
+        .locate(root, &needles.iter().map(String::as_str).collect::<Vec<_>>())
+}
+
+pub fn pack(err: PackError, root: &Path) -> Diagnostic {
+    match err {
+        PackError::NonUtf8(path) => Diagnostic::error(String::from("Can't pack non UTF-8 file"))
+            .in_file(path),
+        PackError::InvalidCss(file, err) => stylesheet(err, file, root),
+    }
+}
+
+pub fn write(err: WriteError) -> Diagnostic {
+    match err {
+        WriteError::NotADirectory(path) => Diagnostic::error(format!("Output path `{}` is not a directory", path.display())),
+        WriteError::NotEmpty(path) => Diagnostic::error(format!("Output directory `{}` is not empty", path.display())),
+        WriteError::Io(path, err) => Diagnostic::error(format!("Can't write `{}`: {err}", path.display())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::diagnostics::{find_word, sarif, Diagnostic, Location, Stage};
+
+    #[test]
+    fn renders_snippet_and_context() {
+        let diagnostic = Diagnostic {
+            location: Location::from_offset("steps:\n  - \"Fill data\":\n    text:\n", 28, 4).map(Box::new),
+            ..Diagnostic::error(String::from("File `pages/missing.md` doesn't exist"))
+                .in_file("pages/index.yml")
+                .with_context(vec![String::from("in step \"Fill data\""), String::from("while generating variable `text`")])
+        };
+        assert_eq!(diagnostic.file, Some(PathBuf::from("pages/index.yml")));
+        assert_eq!(diagnostic.render(false), r#"error: File `pages/missing.md` doesn't exist
+ --> pages/index.yml:3:5
+  |
+3 |     text:
+  |     ^^^^
+  = in step "Fill data"
+  = while generating variable `text`"#);
+    }
+
+    #[test]
+    fn finds_whole_words() {
+        assert_eq!(find_word("context: a\ntext: b", "text"), Some(11));
+        assert_eq!(find_word("components/blog-entry", "components/blog"), None);
+        assert_eq!(find_word("path: blog/a.md", "blog/a.md"), Some(6));
+    }
+
+    #[test]
+    fn sarif_marks_region() {
+        let diagnostic = Diagnostic {
+            location: Location::from_offset("template: missing.html\n", 10, 12).map(Box::new),
+            ..Diagnostic::error(String::from("Template `missing.html` doesn't exist"))
+                .in_file("pages/index.yml")
+        };
+        let sarif: serde_json::Value = serde_json::from_str(&sarif(&[diagnostic], Some(Stage::Build))).unwrap();
+        let result = &sarif["runs"][0]["results"][0];
+        assert_eq!(result["ruleId"], "build");
+        assert_eq!(result["level"], "error");
+        let location = &result["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "pages/index.yml");
+        assert_eq!(location["region"]["startLine"], 1);
+        assert_eq!(location["region"]["startColumn"], 11);
+        assert_eq!(location["region"]["endColumn"], 23);
+    }
+}
@@ -0,0 +1,235 @@
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, FixedOffset, NaiveDate};
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::builder::Generated;
+
+/// Html tags removed by the `striptags` filter.
+static TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+/// `datetime` attribute of the `<time>` elements generated for timestamps.
+static TIME_ELEMENT: Lazy<Regex> = Lazy::new(|| Regex::new(r#"<time datetime="([^"]*)""#).unwrap());
+
+/// A transformation of a variable value in a `{{ name | filter }}` tag.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// Replaces blank and undefined values.
+    Default(String),
+    /// Maximum number of characters, longer values end with `…`.
+    Truncate(usize),
+    Upper,
+    Lower,
+    /// Lowercase ascii letters and digits separated by `-`.
+    Slugify,
+    /// Removes html tags.
+    StripTags,
+    /// Formats a date or timestamp with a strftime format.
+    Date(String),
+    /// Encodes the value as json string, lists as json array.
+    Json,
+    /// Inserts the value without escaping.
+    Raw,
+}
+
+impl Filter {
+    /// Parse the filters of a tag, like ` | truncate(20) | upper`.
+    pub fn parse_pipeline(pipeline: &str) -> Result<Vec<Filter>, FilterParseError> {
+        split_pipeline(pipeline).into_iter()
+            .filter(|filter| !filter.is_empty())
+            .map(Filter::parse)
+            .collect()
+    }
+
+    fn parse(filter: &str) -> Result<Filter, FilterParseError> {
+        let (name, argument) = match filter.split_once('(') {
+            None => (filter, None),
+            Some((name, argument)) => (name.trim(), Some(argument.strip_suffix(')').unwrap_or(argument).trim())),
+        };
+        let invalid = |expected| Err(FilterParseError::InvalidArgument(name.to_string(), expected));
+        Ok(match (name, argument) {
+            ("default", Some(argument)) => match string_argument(argument) {
+                None => return invalid("a quoted text"),
+                Some(text) => Filter::Default(text),
+            },
+            ("truncate", Some(argument)) => match argument.parse() {
+                Err(_) => return invalid("a number of characters"),
+                Ok(length) => Filter::Truncate(length),
+            },
+            ("date", Some(argument)) => match string_argument(argument) {
+                Some(format) if StrftimeItems::new(&format).all(|item| item != Item::Error) => Filter::Date(format),
+                _ => return invalid("a quoted strftime format like \"%Y-%m-%d\""),
+            },
+            ("upper", None) => Filter::Upper,
+            ("lower", None) => Filter::Lower,
+            ("slugify", None) => Filter::Slugify,
+            ("striptags", None) => Filter::StripTags,
+            ("json", None) => Filter::Json,
+            ("raw", None) => Filter::Raw,
+            ("default" | "truncate" | "date", None) => return invalid("an argument"),
+            ("upper" | "lower" | "slugify" | "striptags" | "json" | "raw", Some(_)) => return invalid("no argument"),
+            _ => return Err(FilterParseError::UnknownFilter(name.to_string())),
+        })
+    }
+
+    /// Apply the filter to a value, lists are transformed item by item
+    /// except by `default` and `json`.
+    pub fn apply(&self, value: Generated) -> Result<Generated, FilterError> {
+        Ok(match self {
+            Filter::Default(default) => match value.is_truthy() {
+                true => value,
+                false => Generated::Text(default.clone()),
+            },
+            Filter::Truncate(length) => value.map(&|text| match text.char_indices().nth(*length) {
+                None => text,
+                Some((end, _)) => format!("{}…", text[..end].trim_end()),
+            }),
+            Filter::Upper => value.map(&|text| text.to_uppercase()),
+            Filter::Lower => value.map(&|text| text.to_lowercase()),
+            Filter::Slugify => value.map(&|text| text.to_lowercase()
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<_>>()
+                .join("-")),
+            Filter::StripTags => value.map(&|text| TAG.replace_all(&text, "").to_string()),
+            Filter::Date(format) => date(value, format)?,
+            Filter::Json => Generated::Text(json(value).to_string()),
+            Filter::Raw => raw(value),
+        })
+    }
+
+    /// Whether the filter makes undefined variables usable.
+    pub fn is_default(&self) -> bool {
+        matches!(self, Filter::Default(_))
+    }
+}
+
+#[derive(Debug)]
+pub enum FilterParseError {
+    UnknownFilter(String),
+    /// Filter name, expected argument
+    InvalidArgument(String, &'static str),
+}
+
+#[derive(Debug)]
+pub enum FilterError {
+    /// Value the `date` filter can't read.
+    NotADate(String),
+}
+
+fn date(value: Generated, format: &str) -> Result<Generated, FilterError> {
+    match value {
+        Generated::Text(text) | Generated::Html(text) => match parse_date(&text) {
+            None => Err(FilterError::NotADate(text)),
+            Some(date) => Ok(Generated::Text(date.format(format).to_string())),
+        },
+        Generated::List(items) => items.into_iter()
+            .map(|item| date(item, format))
+            .collect::<Result<_, _>>()
+            .map(Generated::List),
+    }
+}
+
+fn json(value: Generated) -> serde_json::Value {
+    match value {
+        Generated::Text(text) | Generated::Html(text) => serde_json::Value::String(text),
+        Generated::List(items) => serde_json::Value::Array(items.into_iter().map(json).collect()),
+    }
+}
+
+fn raw(value: Generated) -> Generated {
+    match value {
+        Generated::Text(text) => Generated::Html(text),
+        Generated::List(items) => Generated::List(items.into_iter().map(raw).collect()),
+        html => html,
+    }
+}
+
+/// Split a pipeline at `|` outside of quoted arguments.
+fn split_pipeline(pipeline: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in pipeline.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => quoted = !quoted,
+            '|' if !quoted => {
+                parts.push(pipeline[start..i].trim());
+                start = i + 1;
+            },
+            _ => {},
+        }
+    }
+    parts.push(pipeline[start..].trim());
+    parts
+}
+
+/// Unescaped content of a `"` quoted argument.
+fn string_argument(argument: &str) -> Option<String> {
+    let content = argument.strip_prefix('"')?.strip_suffix('"')?;
+    let mut text = String::with_capacity(content.len());
+    let mut chars = content.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.push(chars.next()?),
+            '"' => return None,
+            c => text.push(c),
+        }
+    }
+    Some(text)
+}
+
+/// Read a `<time>` element, a RFC 3339 date, a `YYYY-MM-DD` day or a unix
+/// timestamp.
+fn parse_date(text: &str) -> Option<DateTime<FixedOffset>> {
+    let text = TIME_ELEMENT.captures(text)
+        .map_or(text.trim(), |time| time.get(1).unwrap().as_str());
+    if let Ok(date) = DateTime::parse_from_rfc3339(text) {
+        return Some(date);
+    }
+    if let Ok(day) = NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+        return Some(day.and_hms_opt(0, 0, 0)?.and_utc().fixed_offset());
+    }
+    text.parse().ok()
+        .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0))
+        .map(|date| date.fixed_offset())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::Generated;
+    use crate::filters::{Filter, FilterParseError};
+    use crate::html::Context;
+
+    fn apply(pipeline: &str, value: &str) -> String {
+        Filter::parse_pipeline(pipeline).unwrap().iter()
+            .try_fold(Generated::Text(value.to_string()), |value, filter| filter.apply(value))
+            .unwrap()
+            .output(&Context::Text)
+    }
+
+    #[test]
+    fn applies_filters() {
+        assert_eq!(apply(r#" | default("Untitled | \"new\"") | upper"#, " "), "UNTITLED | \"NEW\"");
+        assert_eq!(apply(" | striptags | truncate(11)", "<p>Hacking <em>window</em> movement</p>"), "Hacking win…");
+        assert_eq!(apply(" | slugify", "How many lines? (AOSP 14)"), "how-many-lines-aosp-14");
+        assert_eq!(apply(r#" | date("%d.%m.%Y")"#, r#"<time datetime="2024-01-27T22:20:48+00:00">2024-01-27</time>"#), "27.01.2024");
+        assert_eq!(apply(r#" | date("%Y")"#, "1706394048"), "2024");
+        assert_eq!(apply(" | json", "say \"hi\""), r#""say \"hi\"""#);
+        assert_eq!(apply(" | upper", "<b>"), "&lt;B&gt;");
+        assert_eq!(apply(" | upper | raw", "<b>"), "<B>");
+        let list = Generated::List(vec![Generated::Text(String::from("a")), Generated::Html(String::from("<b>"))]);
+        assert_eq!(Filter::Json.apply(list).unwrap(), Generated::Text(String::from(r#"["a","<b>"]"#)));
+    }
+
+    #[test]
+    fn rejects_unknown_filters() {
+        assert!(matches!(Filter::parse_pipeline(" | uppercase"), Err(FilterParseError::UnknownFilter(name)) if name == "uppercase"));
+        assert!(matches!(Filter::parse_pipeline(" | truncate(many)"), Err(FilterParseError::InvalidArgument(..))));
+        assert!(matches!(Filter::parse_pipeline(r#" | date("%Q")"#), Err(FilterParseError::InvalidArgument(..))));
+        assert!(matches!(Filter::parse_pipeline(" | default"), Err(FilterParseError::InvalidArgument(..))));
+    }
+}
@@ -1,9 +1,13 @@
 use std::{fs, io};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
 use either::Either;
+use itertools::Itertools;
 
-use crate::builder::{BuildProcedure, BuildProcedureLoadError};
+use crate::build_cache::fingerprint;
+use crate::builder::{BuildProcedure, BuildProcedureLoadError, DEFAULTS_FILE};
+use crate::source_dir::colliding_name;
 
 #[derive(Debug)]
 pub struct FsTree {
@@ -15,14 +19,27 @@ pub struct FsTree {
 }
 
 impl FsTree {
-    pub fn load(path: &PathBuf) -> Result<Self, FsTreeLoadError> {
+    /// Load the file or directory at [path].
+    ///
+    /// Git timestamps of files present in [created_cache] are not queried
+    /// again, newly queried timestamps get added to it.
+    pub fn load(path: &PathBuf, created_cache: &mut HashMap<PathBuf, u64>) -> Result<Self, FsTreeLoadError> {
         if path.is_file() {
-            let content = match fs::read_to_string(&path) {
+            let content = match fs::read_to_string(path) {
                 Err(err) => return Err(FsTreeLoadError::CantReadPath(path.clone(), err)),
                 Ok(content) => content,
             };
             if let Some(Some(file_name)) = path.file_name().map(|f| f.to_str()) {
-                let created = Self::get_added_to_git_date(path.canonicalize().unwrap_or_else(|_| path.clone()));
+                let created = match created_cache.get(path) {
+                    Some(created) => Some(*created),
+                    None => {
+                        let created = Self::get_added_to_git_date(path.canonicalize().unwrap_or_else(|_| path.clone()));
+                        if let Some(created) = created {
+                            created_cache.insert(path.clone(), created);
+                        }
+                        created
+                    },
+                };
                 Ok(FsTree {
                     entry_name: file_name.to_string(),
                     child: Either::Left(content),
@@ -33,7 +50,7 @@ impl FsTree {
             }
 
         } else if path.is_dir() {
-            let read_dir = match fs::read_dir(&path) {
+            let read_dir = match fs::read_dir(path) {
                 Err(err) => return Err(FsTreeLoadError::CantReadPath(path.clone(), err)),
                 Ok(c) => c
             };
@@ -42,7 +59,7 @@ impl FsTree {
                 match e {
                     Err(err) => return Err(FsTreeLoadError::CantReadPath(path.clone(), err)),
                     Ok(e) => {
-                        let subtree = FsTree::load(&e.path())?;
+                        let subtree = FsTree::load(&e.path(), created_cache)?;
                         children.push(subtree);
                     }
                 }
@@ -65,7 +82,8 @@ impl FsTree {
         let out = std::process::Command::new("git")
             .arg("log")
             .arg("--pretty=format:%at") // https://git-scm.com/docs/pretty-formats#Documentation/pretty-formats.txt-ematem
-            .arg(path)
+            .arg(&path)
+            .current_dir(path.parent()?)
             .output().ok()?;
         if !out.status.success() {
             return None;
@@ -75,15 +93,34 @@ impl FsTree {
         out.parse().ok()
     }
 
+    /// Parse this tree and merge the build procedures included by others
+    /// into them.
     pub fn parse(self) -> Result<ParsedFsTree, ParsedFsTreeParseError> {
+        let mut tree = self.parse_in(Path::new(""))?;
+        tree.check_page_names()?;
+        tree.resolve_includes()?;
+        Ok(tree)
+    }
+
+    /// Parse this tree which is located in the [parent] directory.
+    fn parse_in(self, parent: &Path) -> Result<ParsedFsTree, ParsedFsTreeParseError> {
+        let path = parent.join(&self.entry_name);
         match self.child {
             Either::Left(content) => {
+                let hash = fingerprint(&content);
                 let content = match PathBuf::from(&self.entry_name).extension().and_then(|e|e.to_str()) {
                     None => Ok(ParsedFsEntry::TextFile(content)),
                     Some("yml") => {
                         match BuildProcedure::new(content.as_str()) {
                             Ok(procedure) => Ok(ParsedFsEntry::BuildProcedure(procedure)),
-                            Err(err) => Err(ParsedFsTreeParseError::InvalidBuildProcedure(PathBuf::from(&self.entry_name), err)),
+                            Err(err) => Err(ParsedFsTreeParseError::InvalidBuildProcedure(path, err)),
+                        }
+                    },
+                    Some("md") => {
+                        match BuildProcedure::from_markdown(content.as_str()) {
+                            None => Ok(ParsedFsEntry::TextFile(content)),
+                            Some(Ok(procedure)) => Ok(ParsedFsEntry::BuildProcedure(procedure)),
+                            Some(Err(err)) => Err(ParsedFsTreeParseError::InvalidBuildProcedure(path, err)),
                         }
                     },
                     Some(_) => Ok(ParsedFsEntry::TextFile(content)),
@@ -92,18 +129,24 @@ impl FsTree {
                     name: self.entry_name,
                     content,
                     created: self.created,
+                    hash,
                 })
             }
             Either::Right(children) => {
                 let mut parsed = Vec::new();
                 for child in children {
-                    let child = child.parse()?;
+                    let child = child.parse_in(&path)?;
                     parsed.push(child);
                 }
+                let hash = fingerprint(&parsed.iter()
+                    .map(|c| (&c.name, c.created, c.hash))
+                    .sorted()
+                    .collect::<Vec<_>>());
                 Ok(ParsedFsTree {
                     name: self.entry_name,
                     content: ParsedFsEntry::Directory(parsed),
                     created: self.created,
+                    hash,
                 })
             }
         }
@@ -123,7 +166,9 @@ pub enum FsTreeLoadError {
 pub struct ParsedFsTree {
     pub name: String,
     pub content: ParsedFsEntry,
-    pub created: Option<u64>
+    pub created: Option<u64>,
+    /// Fingerprint of the file content or of all children for directories.
+    pub hash: u64,
 }
 
 #[derive(Debug, Clone)]
@@ -135,46 +180,142 @@ pub enum ParsedFsEntry {
 
 #[derive(Debug)]
 pub enum ParsedFsTreeParseError {
-    InvalidBuildProcedure(PathBuf, BuildProcedureLoadError)
+    /// Path including the name of the root tree, err
+    InvalidBuildProcedure(PathBuf, BuildProcedureLoadError),
+    /// Path of the build procedure, included path that is no build procedure
+    MissingInclude(PathBuf, String),
+    /// Build procedures that include each other, starting and ending with the
+    /// same one.
+    CyclicIncludes(Vec<PathBuf>),
+    /// Page, other page that builds the same html file
+    CollidingPages(PathBuf, PathBuf),
 }
 
 impl ParsedFsTree {
-    /// Gather all files with a matching [file_extension] and return their paths and content.
+    /// Make sure no two pages build the same html file.
+    fn check_page_names(&self) -> Result<(), ParsedFsTreeParseError> {
+        let pages = self.procedures().into_iter()
+            .filter(|(path, _)| !path.file_name().and_then(|name| name.to_str()).is_some_and(BuildProcedure::is_partial))
+            .map(|(path, _)| path.to_string_lossy().replace('\\', "/"))
+            .collect::<Vec<_>>();
+        let html = |page: &str| Path::new(page).with_extension("html").to_string_lossy().to_string();
+        match colliding_name(pages.iter(), html) {
+            Some((page, other)) => Err(ParsedFsTreeParseError::CollidingPages(PathBuf::from(page), PathBuf::from(other))),
+            None => Ok(()),
+        }
+    }
+
+    /// Replace every build procedure of this tree by the procedure merged
+    /// with the procedures it includes.
+    fn resolve_includes(&mut self) -> Result<(), ParsedFsTreeParseError> {
+        let procedures = self.procedures().into_iter().collect::<HashMap<_, _>>();
+        let mut merged = HashMap::new();
+        for path in procedures.keys() {
+            self.merge_procedure(path, &procedures, &mut merged, &mut Vec::new())?;
+        }
+        self.replace_procedures(Path::new(""), &mut merged);
+        Ok(())
+    }
+
+    /// Add the procedure at [path] merged with its includes, and for pages
+    /// with the defaults files of their directories, to [merged].
+    ///
+    /// [chain] holds the procedures that include [path], starting with the
+    /// one that is merged.
+    fn merge_procedure(&self, path: &PathBuf, procedures: &HashMap<PathBuf, BuildProcedure>, merged: &mut HashMap<PathBuf, BuildProcedure>, chain: &mut Vec<PathBuf>) -> Result<(), ParsedFsTreeParseError> {
+        if merged.contains_key(path) {
+            return Ok(());
+        }
+        if let Some(start) = chain.iter().position(|including| including == path) {
+            let mut cycle = chain[start..].to_vec();
+            cycle.push(path.clone());
+            return Err(ParsedFsTreeParseError::CyclicIncludes(cycle));
+        }
+        let procedure = &procedures[path];
+        chain.push(path.clone());
+        let mut defaults_paths = Vec::new();
+        let is_partial = path.file_name().and_then(|name| name.to_str()).is_some_and(BuildProcedure::is_partial);
+        if !is_partial {
+            for dir in path.ancestors().skip(1).collect::<Vec<_>>().into_iter().rev() {
+                let defaults_path = dir.join(DEFAULTS_FILE);
+                if procedures.contains_key(&defaults_path) {
+                    self.merge_procedure(&defaults_path, procedures, merged, chain)?;
+                }
+                if let Ok(relative) = defaults_path.strip_prefix(&self.name) {
+                    defaults_paths.push((relative.to_str().unwrap().to_string(), defaults_path));
+                }
+            }
+        }
+        let mut included_paths = Vec::new();
+        for include in procedure.includes() {
+            let include_path = PathBuf::from(&self.name).join(include);
+            if !procedures.contains_key(&include_path) {
+                return Err(ParsedFsTreeParseError::MissingInclude(path.clone(), include.clone()));
+            }
+            self.merge_procedure(&include_path, procedures, merged, chain)?;
+            included_paths.push(include_path);
+        }
+        chain.pop();
+        let defaults = defaults_paths.into_iter()
+            .map(|(relative, defaults_path)| (relative, merged.get(&defaults_path)))
+            .collect::<Vec<_>>();
+        let included = included_paths.iter()
+            .map(|include_path| &merged[include_path])
+            .collect::<Vec<_>>();
+        let procedure = procedure.merged(&defaults, &included);
+        merged.insert(path.clone(), procedure);
+        Ok(())
+    }
+
+    /// Replace the build procedures of this tree, which is located in the
+    /// [parent] directory, by the ones in [procedures].
+    fn replace_procedures(&mut self, parent: &Path, procedures: &mut HashMap<PathBuf, BuildProcedure>) {
+        let path = parent.join(&self.name);
+        match &mut self.content {
+            ParsedFsEntry::Directory(children) => for child in children {
+                child.replace_procedures(&path, procedures);
+            },
+            ParsedFsEntry::BuildProcedure(procedure) => if let Some(merged) = procedures.remove(&path) {
+                *procedure = merged;
+            },
+            ParsedFsEntry::TextFile(_) => {},
+        }
+    }
+
+    /// Gather all build procedures, from yml files and markdown pages, and
+    /// return their paths.
     ///
     /// Paths are relative to this [ParsedFsTree]s parent directory.
-    pub fn filter(&self, file_extension: &str) -> Vec<(PathBuf, ParsedFsEntry)> {
+    pub fn procedures(&self) -> Vec<(PathBuf, BuildProcedure)> {
         let path = PathBuf::from(&self.name);
         match &self.content {
             ParsedFsEntry::Directory(children) => children.iter()
-                .flat_map(|c| c
-                    .filter(file_extension)
-                    .iter()
-                    .map(|(child_path, entry)| (path.join(child_path), entry.clone()))
-                    .collect::<Vec<_>>()
-                )
-                .collect::<Vec<(PathBuf, ParsedFsEntry)>>()
-            ,
-            content => {
-                if path.extension().is_some_and(|e| e.to_str().is_some_and(|e| e == file_extension)) {
-                    vec![(path, content.clone())]
-                } else {
-                    Vec::new()
-                }
-            },
+                .flat_map(|c| c.procedures().into_iter()
+                    .map(|(child_path, procedure)| (path.join(child_path), procedure)))
+                .collect(),
+            ParsedFsEntry::BuildProcedure(procedure) => vec![(path, procedure.clone())],
+            ParsedFsEntry::TextFile(_) => Vec::new(),
         }
     }
 
-    pub fn get(&self, path: &String) -> Option<ParsedFsEntry> {
-        if &self.name == path { // only happens at the end
-            Some(self.content.clone())
+    pub fn get(&self, path: &str) -> Option<ParsedFsEntry> {
+        self.get_tree(path).map(|tree| tree.content.clone())
+    }
+
+    /// Find the entry at a [path] that starts with the name of this tree.
+    pub fn get_tree(&self, path: &str) -> Option<&ParsedFsTree> {
+        if self.name == path { // only happens at the end
+            Some(self)
         } else if let ParsedFsEntry::Directory(children) = &self.content {
             let mut path_parts = path.splitn(2, '/');
             let dir_name = path_parts.next();
             let path= path_parts.next();
-            if dir_name.is_some() && path.is_some() && self.name.as_str() == dir_name.unwrap() {
-                let path = path.unwrap().to_string();
+            if let (Some(dir_name), Some(path)) = (dir_name, path) {
+                if self.name.as_str() != dir_name {
+                    return None;
+                }
                 for e in children {
-                    let e = e.get(&path);
+                    let e = e.get_tree(path);
                     if e.is_some() {
                         return e;
                     }
@@ -0,0 +1,420 @@
+use std::borrow::Cow;
+
+use html5ever::{driver, local_name, namespace_url, ns, Attribute, ExpandedName, ParseOpts, QualName};
+use html5ever::interface::{ElementFlags, NextParserState, NodeOrText, QuirksMode, TreeSink};
+use html5ever::tendril::{StrTendril, TendrilSink};
+use html5ever::tokenizer::TokenizerOpts;
+use html5ever::tree_builder::TreeBuilderOpts;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use scraper::Html;
+
+/// Debug representation of a tag token in exact parser errors.
+static TAG_TOKEN: Lazy<Regex> = Lazy::new(|| Regex::new(r"Tag \{ kind: (StartTag|EndTag), name: Atom\(\\?'([^'\\]*)\\?' type=\w+\), .*? \}")
+    .unwrap());
+
+/// Debug representation of an element name in exact parser errors.
+static ATOM: Lazy<Regex> = Lazy::new(|| Regex::new(r"Atom\(\\?'([^'\\]*)\\?' type=\w+\)").unwrap());
+
+/// A problem the html parser recovered from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HtmlError {
+    /// Line at which the parser noticed the problem, starting at 1.
+    pub line: usize,
+    pub message: Cow<'static, str>,
+}
+
+/// Parse a complete html document and collect all parse errors.
+pub fn parse_document(document: &str) -> (Html, Vec<HtmlError>) {
+    let sink = PositionedSink::new(Html::new_document());
+    let parsed = driver::parse_document(sink, options()).one(document);
+    (parsed.html, parsed.errors)
+}
+
+/// Parse html that is inserted into the `<body>` of a document and collect
+/// all parse errors.
+pub fn parse_fragment(fragment: &str) -> (Html, Vec<HtmlError>) {
+    let sink = PositionedSink::new(Html::new_fragment());
+    let context = QualName::new(None, ns!(html), local_name!("body"));
+    let parsed = driver::parse_fragment(sink, options(), context, Vec::new()).one(fragment);
+    (parsed.html, parsed.errors)
+}
+
+fn options() -> ParseOpts {
+    ParseOpts {
+        tokenizer: TokenizerOpts {
+            exact_errors: true,
+            ..TokenizerOpts::default()
+        },
+        tree_builder: TreeBuilderOpts {
+            exact_errors: true,
+            ..TreeBuilderOpts::default()
+        },
+    }
+}
+
+/// Replace debug output of tokens in parser errors with html.
+fn readable(message: Cow<'static, str>) -> Cow<'static, str> {
+    if !message.contains("Atom(") {
+        return message;
+    }
+    let message = TAG_TOKEN.replace_all(&message, |captures: &regex::Captures| match &captures[1] {
+        "EndTag" => format!("</{}>", &captures[2]),
+        _ => format!("<{}>", &captures[2]),
+    });
+    Cow::Owned(ATOM.replace_all(&message, "<$1>").to_string())
+}
+
+/// Attributes whose value is a url.
+const URL_ATTRIBUTES: [&str; 9] = ["href", "src", "action", "formaction", "poster", "cite", "data", "background", "ping"];
+
+/// Elements whose content is script or style text instead of html.
+const RAW_TEXT_ELEMENTS: [&str; 2] = ["script", "style"];
+
+/// Url schemes that run code when the url is opened.
+const UNSAFE_SCHEMES: [&str; 2] = ["javascript", "vbscript"];
+
+/// Url inserted instead of urls with an [UNSAFE_SCHEMES] scheme.
+const INVALID_URL: &str = "about:invalid";
+
+/// Part of a document a value gets inserted into.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Context {
+    /// Element content or comments.
+    Text,
+    /// Attribute value, with its quote unless it is unquoted.
+    Attribute(Option<char>),
+    /// Value of an attribute that contains a url.
+    Url {
+        quote: Option<char>,
+        /// Nothing precedes the inserted value in the attribute.
+        at_start: bool,
+    },
+    /// Content of `<script>` and `<style>` elements.
+    RawText,
+}
+
+/// Tracks the [Context] while reading a document piece by piece.
+#[derive(Debug, Clone, Default)]
+pub struct ContextScanner {
+    state: State,
+}
+
+#[derive(Debug, Clone, Default)]
+enum State {
+    #[default]
+    Data,
+    /// After `<`.
+    TagOpen,
+    /// After `<!`, with the number of `-` read. More than two means it is no
+    /// comment.
+    Declaration(usize),
+    /// Comment content, with the number of `-` read right before.
+    Comment(usize),
+    TagName { name: String, closing: bool },
+    /// Between the attributes of a tag.
+    InTag { name: String, closing: bool },
+    AttributeName { tag: String, attribute: String },
+    /// After the `=` of an attribute.
+    BeforeValue { tag: String, attribute: String },
+    Value { tag: String, attribute: String, quote: Option<char>, empty: bool },
+    /// Content of a raw text element, with the part of its end tag read so far.
+    RawText { tag: String, end: String },
+}
+
+impl ContextScanner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Continue reading the document with [html].
+    pub fn scan(&mut self, html: &str) {
+        for c in html.chars() {
+            self.state = Self::next(std::mem::take(&mut self.state), c);
+        }
+    }
+
+    fn next(state: State, c: char) -> State {
+        let tag_end = |name: String, closing: bool| match !closing && RAW_TEXT_ELEMENTS.contains(&name.as_str()) {
+            true => State::RawText { tag: name, end: String::new() },
+            false => State::Data,
+        };
+        match state {
+            State::Data => match c {
+                '<' => State::TagOpen,
+                _ => State::Data,
+            },
+            State::TagOpen => match c {
+                '!' => State::Declaration(0),
+                '/' => State::TagName { name: String::new(), closing: true },
+                c if c.is_ascii_alphabetic() => State::TagName { name: c.to_ascii_lowercase().to_string(), closing: false },
+                '<' => State::TagOpen,
+                _ => State::Data,
+            },
+            State::Declaration(dashes) => match c {
+                '>' => State::Data,
+                '-' if dashes == 1 => State::Comment(0),
+                '-' if dashes == 0 => State::Declaration(1),
+                _ => State::Declaration(3),
+            },
+            State::Comment(dashes) => match c {
+                '>' if dashes >= 2 => State::Data,
+                '-' => State::Comment(dashes + 1),
+                _ => State::Comment(0),
+            },
+            State::TagName { mut name, closing } => match c {
+                '>' => tag_end(name, closing),
+                c if c.is_whitespace() || c == '/' => State::InTag { name, closing },
+                c => {
+                    name.push(c.to_ascii_lowercase());
+                    State::TagName { name, closing }
+                },
+            },
+            State::InTag { name, closing } => match c {
+                '>' => tag_end(name, closing),
+                c if c.is_whitespace() || c == '/' => State::InTag { name, closing },
+                c => State::AttributeName { tag: name, attribute: c.to_ascii_lowercase().to_string() },
+            },
+            State::AttributeName { tag, mut attribute } => match c {
+                '>' => tag_end(tag, false),
+                '=' => State::BeforeValue { tag, attribute },
+                c if c.is_whitespace() || c == '/' => State::InTag { name: tag, closing: false },
+                c => {
+                    attribute.push(c.to_ascii_lowercase());
+                    State::AttributeName { tag, attribute }
+                },
+            },
+            State::BeforeValue { tag, attribute } => match c {
+                '>' => tag_end(tag, false),
+                '"' | '\'' => State::Value { tag, attribute, quote: Some(c), empty: true },
+                c if c.is_whitespace() => State::BeforeValue { tag, attribute },
+                _ => State::Value { tag, attribute, quote: None, empty: false },
+            },
+            State::Value { tag, attribute, quote, .. } => match (c, quote) {
+                (c, Some(quote)) if c == quote => State::InTag { name: tag, closing: false },
+                ('>', None) => tag_end(tag, false),
+                (c, None) if c.is_whitespace() => State::InTag { name: tag, closing: false },
+                _ => State::Value { tag, attribute, quote, empty: false },
+            },
+            State::RawText { tag, mut end } => {
+                if c == '<' {
+                    return State::RawText { tag, end: String::from("<") };
+                }
+                if end.is_empty() {
+                    return State::RawText { tag, end };
+                }
+                end.push(c.to_ascii_lowercase());
+                let end_tag = format!("</{tag}");
+                match (end == end_tag, end_tag.starts_with(&end)) {
+                    (true, _) => State::TagName { name: tag, closing: true },
+                    (false, true) => State::RawText { tag, end },
+                    (false, false) => State::RawText { tag, end: String::new() },
+                }
+            },
+        }
+    }
+
+    /// Context of the position up to which the document was read.
+    pub fn context(&self) -> Context {
+        let is_url = |attribute: &String| URL_ATTRIBUTES.contains(&attribute.as_str());
+        match &self.state {
+            State::Data | State::TagOpen | State::Declaration(_) | State::Comment(_) => Context::Text,
+            State::TagName { .. } | State::InTag { .. } | State::AttributeName { .. } => Context::Attribute(None),
+            State::BeforeValue { attribute, .. } if is_url(attribute) => Context::Url { quote: None, at_start: true },
+            State::BeforeValue { .. } => Context::Attribute(None),
+            State::Value { attribute, quote, empty, .. } if is_url(attribute) => Context::Url { quote: *quote, at_start: *empty },
+            State::Value { quote, .. } => Context::Attribute(*quote),
+            State::RawText { .. } => Context::RawText,
+        }
+    }
+}
+
+/// Escape text so it is read as is when inserted at [context].
+pub fn escape(text: &str, context: &Context) -> String {
+    match context {
+        Context::Text => text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;"),
+        Context::Attribute(quote) => text.chars()
+            .map(|c| match (c, quote) {
+                ('&', _) => String::from("&amp;"),
+                ('<', _) => String::from("&lt;"),
+                ('>', _) => String::from("&gt;"),
+                ('"', Some('"') | None) => String::from("&quot;"),
+                ('\'', Some('\'') | None) => String::from("&#39;"),
+                ('=' | '`', None) => format!("&#{};", c as u32),
+                (c, None) if c.is_whitespace() => format!("&#{};", c as u32),
+                (c, _) => c.to_string(),
+            })
+            .collect(),
+        Context::Url { quote, at_start } => {
+            let scheme = text.trim_start().split_once(':')
+                .map(|(scheme, _)| scheme.to_ascii_lowercase())
+                .filter(|scheme| !scheme.contains(['/', '?', '#']));
+            if *at_start && scheme.is_some_and(|scheme| UNSAFE_SCHEMES.contains(&scheme.as_str())) {
+                return String::from(INVALID_URL);
+            }
+            let encoded: String = text.bytes()
+                .map(|b| match b.is_ascii_alphanumeric() || b"-._~:/?#[]@!$&'()*+,;=%".contains(&b) {
+                    true => (b as char).to_string(),
+                    false => format!("%{b:02X}"),
+                })
+                .collect();
+            escape(&encoded, &Context::Attribute(*quote))
+        },
+        Context::RawText => text.replace("</", "<\\/"),
+    }
+}
+
+/// Builds a scraper [Html] and remembers at which line errors occur.
+struct PositionedSink {
+    html: Html,
+    line: usize,
+    errors: Vec<HtmlError>,
+}
+
+impl PositionedSink {
+    fn new(html: Html) -> Self {
+        PositionedSink { html, line: 1, errors: Vec::new() }
+    }
+}
+
+impl TreeSink for PositionedSink {
+    type Handle = <Html as TreeSink>::Handle;
+    type Output = Self;
+
+    fn finish(self) -> Self {
+        self
+    }
+
+    fn parse_error(&mut self, msg: Cow<'static, str>) {
+        self.errors.push(HtmlError { line: self.line, message: readable(msg) });
+    }
+
+    fn get_document(&mut self) -> Self::Handle {
+        self.html.get_document()
+    }
+
+    fn elem_name<'a>(&'a self, target: &'a Self::Handle) -> ExpandedName<'a> {
+        self.html.elem_name(target)
+    }
+
+    fn create_element(&mut self, name: QualName, attrs: Vec<Attribute>, flags: ElementFlags) -> Self::Handle {
+        self.html.create_element(name, attrs, flags)
+    }
+
+    fn create_comment(&mut self, text: StrTendril) -> Self::Handle {
+        self.html.create_comment(text)
+    }
+
+    fn create_pi(&mut self, target: StrTendril, data: StrTendril) -> Self::Handle {
+        self.html.create_pi(target, data)
+    }
+
+    fn append(&mut self, parent: &Self::Handle, child: NodeOrText<Self::Handle>) {
+        self.html.append(parent, child)
+    }
+
+    fn append_based_on_parent_node(&mut self, element: &Self::Handle, prev_element: &Self::Handle, child: NodeOrText<Self::Handle>) {
+        self.html.append_based_on_parent_node(element, prev_element, child)
+    }
+
+    fn append_doctype_to_document(&mut self, name: StrTendril, public_id: StrTendril, system_id: StrTendril) {
+        self.html.append_doctype_to_document(name, public_id, system_id)
+    }
+
+    fn mark_script_already_started(&mut self, node: &Self::Handle) {
+        self.html.mark_script_already_started(node)
+    }
+
+    fn pop(&mut self, node: &Self::Handle) {
+        self.html.pop(node)
+    }
+
+    fn get_template_contents(&mut self, target: &Self::Handle) -> Self::Handle {
+        self.html.get_template_contents(target)
+    }
+
+    fn same_node(&self, x: &Self::Handle, y: &Self::Handle) -> bool {
+        self.html.same_node(x, y)
+    }
+
+    fn set_quirks_mode(&mut self, mode: QuirksMode) {
+        self.html.set_quirks_mode(mode)
+    }
+
+    fn append_before_sibling(&mut self, sibling: &Self::Handle, new_node: NodeOrText<Self::Handle>) {
+        self.html.append_before_sibling(sibling, new_node)
+    }
+
+    fn add_attrs_if_missing(&mut self, target: &Self::Handle, attrs: Vec<Attribute>) {
+        self.html.add_attrs_if_missing(target, attrs)
+    }
+
+    fn associate_with_form(&mut self, target: &Self::Handle, form: &Self::Handle, nodes: (&Self::Handle, Option<&Self::Handle>)) {
+        self.html.associate_with_form(target, form, nodes)
+    }
+
+    fn remove_from_parent(&mut self, target: &Self::Handle) {
+        self.html.remove_from_parent(target)
+    }
+
+    fn reparent_children(&mut self, node: &Self::Handle, new_parent: &Self::Handle) {
+        self.html.reparent_children(node, new_parent)
+    }
+
+    fn is_mathml_annotation_xml_integration_point(&self, handle: &Self::Handle) -> bool {
+        self.html.is_mathml_annotation_xml_integration_point(handle)
+    }
+
+    fn set_current_line(&mut self, line_number: u64) {
+        self.line = line_number as usize;
+    }
+
+    fn complete_script(&mut self, node: &Self::Handle) -> NextParserState {
+        self.html.complete_script(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::html::{escape, parse_document, parse_fragment, Context, ContextScanner};
+
+    #[test]
+    fn reports_error_lines() {
+        let (_, errors) = parse_document("<!DOCTYPE html>\n<html><body>\n<p>a</span>\n</body></html>");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 3);
+        let (_, errors) = parse_fragment("<article>\n  <p>a</p>\n</article>\n");
+        assert!(errors.is_empty());
+        let (_, errors) = parse_fragment("<p>a</p></main>");
+        assert_eq!(errors[0].message, "Unexpected token </main> in insertion mode InBody");
+    }
+
+    #[test]
+    fn escapes_by_context() {
+        let contexts = |html: &str| {
+            let mut scanner = ContextScanner::new();
+            html.split('|')
+                .map(|part| {
+                    scanner.scan(part);
+                    scanner.context()
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(contexts(r#"<!-- <a href=" -->|<a title='|' href="|?q=|">|</a><script>|</script>"#), vec![
+            Context::Text,
+            Context::Attribute(Some('\'')),
+            Context::Url { quote: Some('"'), at_start: true },
+            Context::Url { quote: Some('"'), at_start: false },
+            Context::Text,
+            Context::RawText,
+            Context::Text,
+        ]);
+
+        assert_eq!(escape("Q&A <b>", &Context::Text), "Q&amp;A &lt;b&gt;");
+        assert_eq!(escape(r#"It's "fine""#, &Context::Attribute(Some('"'))), "It's &quot;fine&quot;");
+        assert_eq!(escape("a b", &Context::Attribute(None)), "a&#32;b");
+        assert_eq!(escape("/search?q=a b&c=\"", &Context::Url { quote: Some('"'), at_start: true }), "/search?q=a%20b&amp;c=%22");
+        assert_eq!(escape(" JavaScript:alert(1)", &Context::Url { quote: Some('"'), at_start: true }), "about:invalid");
+        assert_eq!(escape("\"</script>", &Context::RawText), "\"<\\/script>");
+    }
+}
@@ -1,21 +1,32 @@
-use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use lewp_css::Stylesheet;
+use once_cell::sync::Lazy;
+use regex::Regex;
 use scraper::Html;
 use serde::Deserialize;
 
+use crate::build_cache::{fingerprint, Dependencies};
 use crate::builder;
-use crate::builder::ValueGenerationError;
+use crate::filters::{Filter, FilterError, FilterParseError};
+use crate::css::{Stylesheet, StylesheetParseError, UsedNames};
+use crate::builder::{BuildProcedureLoadError, Generated, GlobalVariables, Value, ValueGenerationError};
 use crate::fs_tree::{FsTree, ParsedFsTree, ParsedFsTreeParseError};
+use crate::html;
+use crate::html::{ContextScanner, HtmlError};
 use crate::source_dir::SourceDir;
+use crate::validator::Level;
 
 /// Intermediate representation of the [SourceDir].
 pub struct IR {
     pub config: WebsiteConf,
 
+    /// Variables of `website.yml` and built-in variables available to every
+    /// page by their name.
+    pub globals: HashMap<String, Value>,
+
     pub templates: HashMap<String, FwHTML>,
 
     pub components: HashMap<String, FwHTML>,
@@ -24,6 +35,9 @@ pub struct IR {
 
     pub style_css: String,
 
+    /// Parsed `layout.css` and `style.css` by their file name.
+    pub stylesheets: Vec<(String, Stylesheet)>,
+
     pub pages: ParsedFsTree,
 
     pub static_assets: Vec<(PathBuf, Vec<u8>)>,
@@ -31,18 +45,24 @@ pub struct IR {
 
 impl IR {
     pub fn new(data: SourceDir) -> Result<Self, SourceFormatError> {
-        let config = Self::load_config(data.website_yml.as_str())?;
+        let mut config = Self::load_config(data.website_yml.as_str())?;
+        let globals = Self::load_globals(&mut config, data.git_commit)?;
         let templates = Self::load_templates(data.templates)?;
         let components = Self::load_components(data.components)?;
         let pages = Self::load_pages(data.pages)?;
-        // TODO: validate pages tree and css
+        let stylesheets = vec![
+            (String::from("layout.css"), Self::load_css(&data.layout_css, "layout.css")?),
+            (String::from("style.css"), Self::load_css(&data.style_css, "style.css")?),
+        ];
 
         Ok(IR{
             config,
+            globals,
             templates,
             components,
             layout_css: data.layout_css,
             style_css: data.style_css,
+            stylesheets,
             pages,
             static_assets: data.static_files,
         })
@@ -53,39 +73,121 @@ impl IR {
             .map_err(|err| SourceFormatError::InvalidYaml(String::from("website.yml"), err))
     }
 
+    /// Take the variables of [config] and add the built-in variables that
+    /// they don't override, [git_commit] is the commit of the sources.
+    fn load_globals(config: &mut WebsiteConf, git_commit: Option<String>) -> Result<HashMap<String, Value>, SourceFormatError> {
+        let mut globals = HashMap::from([
+            (String::from("site-url"), Value::Text(config.site_url())),
+        ]);
+        if let Ok(now) = SystemTime::now().duration_since(UNIX_EPOCH) {
+            globals.insert(String::from("build-time"), Value::UnixTimestamp { value: now.as_secs() });
+        }
+        if let Some(commit) = git_commit {
+            globals.insert(String::from("git-commit"), Value::Text(commit));
+        }
+        match std::mem::take(&mut config.variables).load() {
+            Err(err) => return Err(SourceFormatError::InvalidWebsiteVariable(err)),
+            Ok(variables) => globals.extend(variables),
+        }
+        Ok(globals)
+    }
+
     fn load_templates(data: HashMap<String, String>) -> Result<HashMap<String, FwHTML>, SourceFormatError> {
         let mut loaded = HashMap::new();
         for (k, v) in data {
             match FwHTML::template(v) {
                 Ok(v) => { loaded.insert(k, v); },
-                Err(e) => return Err(SourceFormatError::InvalidTemplateHTML(k, e)),
+                Err(e) => return Err(SourceFormatError::InvalidTemplateHTML(format!("templates/{k}"), e)),
             }
         };
-        Ok(loaded)
+        let mut composed = HashMap::new();
+        for name in loaded.keys() {
+            Self::compose_template(name, &loaded, &mut composed, &mut Vec::new())?;
+        }
+        Ok(composed)
+    }
+
+    /// Add the template [name] to [composed], with the templates it extends
+    /// filled with its blocks.
+    ///
+    /// [chain] holds the templates that extend [name], starting with the one
+    /// that is composed.
+    fn compose_template(name: &str, loaded: &HashMap<String, FwHTML>, composed: &mut HashMap<String, FwHTML>, chain: &mut Vec<String>) -> Result<(), SourceFormatError> {
+        if composed.contains_key(name) {
+            return Ok(());
+        }
+        let file = format!("templates/{name}");
+        let template = &loaded[name];
+        let Some(parent) = &template.parent else {
+            composed.insert(name.to_string(), template.clone());
+            return Ok(());
+        };
+        chain.push(file.clone());
+        let parent_file = format!("templates/{parent}");
+        if let Some(start) = chain.iter().position(|extending| *extending == parent_file) {
+            let mut cycle = chain[start..].to_vec();
+            cycle.push(parent_file);
+            return Err(SourceFormatError::CyclicTemplates(cycle));
+        }
+        if !loaded.contains_key(parent) {
+            return Err(SourceFormatError::MissingParentTemplate(file, parent.clone()));
+        }
+        Self::compose_template(parent, loaded, composed, chain)?;
+        chain.pop();
+
+        let extended = match composed[parent].extended_by(template) {
+            Err(block) => return Err(SourceFormatError::UnknownTemplateBlock(file, block)),
+            Ok(extended) => extended,
+        };
+        if let Err(err) = FwHTML::document(extended.output()) {
+            return Err(SourceFormatError::InvalidComposedTemplate(file, err));
+        }
+        composed.insert(name.to_string(), extended);
+        Ok(())
     }
 
     fn load_components(data: HashMap<String, String>) -> Result<HashMap<String, FwHTML>, SourceFormatError> {
         let mut loaded = HashMap::new();
         for (k, v) in data {
-            match FwHTML::new(v) {
+            match FwHTML::component(v) {
                 Ok(v) => { loaded.insert(k, v); },
-                Err(e) => return Err(SourceFormatError::InvalidTemplateHTML(k, e)),
+                Err(e) => return Err(SourceFormatError::InvalidTemplateHTML(format!("components/{k}.html"), e)),
             }
         };
         Ok(loaded)
     }
 
+    /// Parse a stylesheet and make sure all of its selectors can be matched
+    /// against pages.
     fn load_css(data: &str, filename: &str) -> Result<Stylesheet, SourceFormatError> {
-        // FIXME: wait until css parser supports ":has" attribute
-        match Stylesheet::parse(/*data*/"") {
-            Err(err) => {
-                return Err(SourceFormatError::InvalidCSS(CssParseError {
-                    filename: String::from(filename),
-                    source_location: err.location,
-                    error_class: format!("{:?}", err.kind),
-                }));
+        let stylesheet = match Stylesheet::parse(data) {
+            Err(err) => return Err(SourceFormatError::InvalidCSS(String::from(filename), err)),
+            Ok(css) => css,
+        };
+        for selector in stylesheet.selectors() {
+            if let Err(err) = scraper::Selector::parse(&selector.static_selector()) {
+                return Err(SourceFormatError::InvalidSelector(String::from(filename), selector.source.clone(), err.to_string()));
+            }
+        }
+        Ok(stylesheet)
+    }
+
+    /// Fingerprint of the current state of a [Dependencies] entry.
+    ///
+    /// Returns [None] when the dependency doesn't exist.
+    pub fn fingerprint(&self, dependency: &str) -> Option<u64> {
+        if let Some(name) = dependency.strip_prefix("templates/") {
+            self.templates.get(name).map(|t| fingerprint(&t.output()))
+        } else if let Some(name) = dependency.strip_prefix("components/") {
+            self.components.get(name).map(|c| fingerprint(&c.output()))
+        } else if let Some(name) = dependency.strip_prefix("globals/") {
+            match builder::PAGE_VARIABLES.contains(&name) {
+                // The page path doesn't change for a cached page, the url can
+                true => Some(fingerprint(&self.config.site_url())),
+                false => self.globals.get(name).map(fingerprint),
             }
-            Ok(css) => Ok(css)
+        } else {
+            self.pages.get_tree(dependency).map(|tree| tree.hash)
         }
     }
 
@@ -100,136 +202,590 @@ impl IR {
 #[derive(Debug, Deserialize)]
 pub struct WebsiteConf {
     pub url: String,
+    /// Levels of validation rules by their name.
+    #[serde(default)]
+    pub rules: HashMap<String, Level>,
+    /// Variables every page can use, taken by [IR::new].
+    #[serde(default)]
+    variables: GlobalVariables,
+}
+
+impl WebsiteConf {
+    /// [WebsiteConf::url] with scheme and without trailing `/`, e.g.
+    /// `https://derdilla.com`.
+    pub fn site_url(&self) -> String {
+        let url = self.url.trim_end_matches('/');
+        match url.starts_with("http") {
+            true => url.to_string(),
+            false => format!("https://{url}"),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum SourceFormatError {
     /// Name of the file that has invalid yaml
     InvalidYaml(String, serde_yml::modules::error::Error),
-    /// File name, err
+    /// Path relative to the source root, err
     InvalidTemplateHTML(String, FwHTMLError),
-    /// website data is not in expected format.
-    BadWebsiteYaml,
-    InvalidCSS(CssParseError),
+    /// File name, err
+    InvalidCSS(String, StylesheetParseError),
+    /// File name, selector, parser message
+    InvalidSelector(String, String, String),
+    /// Template, name of the template it extends that doesn't exist
+    MissingParentTemplate(String, String),
+    /// Templates that extend each other, starting and ending with the same
+    /// one.
+    CyclicTemplates(Vec<String>),
+    /// Template, name of a block the templates it extends don't have
+    UnknownTemplateBlock(String, String),
+    /// Template, err of the document it forms with the templates it extends
+    InvalidComposedTemplate(String, FwHTMLError),
+    /// A variable of website.yml can't be loaded.
+    InvalidWebsiteVariable(BuildProcedureLoadError),
     InvalidFsTree(ParsedFsTreeParseError),
 }
 
-#[derive(Debug)]
-pub struct CssParseError {
-    filename: String,
-    source_location: lewp_css::cssparser::SourceLocation,
-    /// A [CustomParseError] debug text.
-    error_class: String,
-}
-
 #[derive(Debug, Clone)]
 pub struct FwHTML {
-    /// Raw html as present in the template
-    data: String,
-
-    used_variables: Vec<String>,
-    used_components: Vec<String>,
-    used_class_names: Vec<String>,
+    /// Text and tags of the html in document order.
+    nodes: Vec<ast::Node>,
+    /// Template whose named blocks are overridden by the blocks of this one.
+    parent: Option<String>,
 }
 
 impl FwHTML {
+    /// Load a complete html document, or the blocks of a template extending
+    /// another one, and fail on parse errors.
     fn template(data: String) -> Result<Self, FwHTMLError> {
+        if let Some((parent, blocks)) = ast::extends(&data) {
+            let nodes = ast::parse(&protect_literals(blocks)?)?;
+            if let Some(content) = ast::content_outside_of_named_blocks(&nodes) {
+                return Err(FwHTMLError::ContentOutsideOfBlocks(content));
+            }
+            return Ok(FwHTML { nodes, parent: Some(parent.to_string()) });
+        }
+        Self::document(data)
+    }
+
+    /// Load a complete html document and fail on parse errors.
+    fn document(data: String) -> Result<Self, FwHTMLError> {
         if !data.starts_with("<!DOCTYPE html>") {
             return Err(FwHTMLError::NotATemplate);
         }
-        Self::new(data)
+        let (_, errors) = html::parse_document(&ast::without_silent_tags(&data));
+        if !errors.is_empty() {
+            return Err(FwHTMLError::DocumentContainsErrors(errors));
+        }
+        Self::new(protect_literals(&data)?)
     }
-    fn new(data: String) -> Result<Self, FwHTMLError> {
-        let document = Html::parse_document(&data);
-        if !document.errors.is_empty() {
-            // TODO: validate html
-            // return Err(FwHTMLError::DocumentContainsErrors(document.errors))
+
+    /// Load html that gets inserted into a `<body>` and fail on parse errors.
+    fn component(data: String) -> Result<Self, FwHTMLError> {
+        let (_, errors) = html::parse_fragment(&data);
+        if !errors.is_empty() {
+            return Err(FwHTMLError::DocumentContainsErrors(errors));
         }
-        let mut classes = analyzer::classes(document);
+        Self::new(protect_literals(&data)?)
+    }
+
+    /// Parse the tags of a html document without checking it for html errors.
+    pub fn new(data: String) -> Result<Self, FwHTMLError> {
+        Ok(FwHTML { nodes: ast::parse(&data)?, parent: None })
+    }
 
-        let variables = analyzer::used_variables(data.as_str());
-        let components = analyzer::used_components(data.as_str());
+    /// This template with its named blocks overridden by the blocks of
+    /// [child] and the declarations of [child] added.
+    ///
+    /// Fails with the name of a block of [child] this template doesn't have.
+    fn extended_by(&self, child: &FwHTML) -> Result<Self, String> {
+        let overrides = ast::named_blocks(&child.nodes);
+        let mut overridden = HashSet::new();
+        let mut nodes = ast::overridden(&self.nodes, &overrides, &mut overridden);
+        nodes.extend(child.nodes.iter()
+            .filter(|node| matches!(node, ast::Node::Declaration(_)))
+            .cloned());
+        match overrides.keys().find(|name| !overridden.contains(**name)) {
+            Some(name) => Err(name.to_string()),
+            None => Ok(FwHTML { nodes, parent: None }),
+        }
+    }
 
-        Ok(FwHTML {
-            data,
-            used_variables: variables,
-            used_components: components,
-            used_class_names: classes,
-        })
+    /// Inserts components and variables and expands blocks in a single pass.
+    /// Tags in inserted values are resolved as part of the same pass.
+    ///
+    /// Blocks whose variable isn't defined yet are kept for later steps.
+    /// Used components are added to [dependencies].
+    pub fn resolved<F>(&self, components: &HashMap<String, FwHTML>, variables: &HashMap<&String, F>, dependencies: &Dependencies) -> Result<Self, FwHTMLResolveError>
+    where
+        F: Fn() -> Result<Generated, builder::ValueGenerationError>,
+    {
+        let lookup = |name: &str| Self::lookup(variables, name);
+        let mut renderer = Renderer::new(Some((components, dependencies)), &lookup, false);
+        let mut nodes = Vec::new();
+        renderer.render(&self.nodes, &Scope::default(), &mut nodes)?;
+        Ok(FwHTML { nodes, parent: None })
     }
 
-    /// Inserts components and variables as long as possible.
-    pub fn resolved<F>(&self, components: &HashMap<String, FwHTML>, variables: &HashMap<&String, F>) -> Result<Self, FwHTMLResolveError>
+    /// Expand the remaining blocks and apply `default` filters after the last
+    /// step, treating variables that are still unknown as undefined.
+    pub fn finalized<F>(&self, variables: &HashMap<&String, F>) -> Result<Self, FwHTMLResolveError>
     where
-        F: Fn() -> Result<String, builder::ValueGenerationError>,
+        F: Fn() -> Result<Generated, builder::ValueGenerationError>,
     {
-        // TODO: proper error propagation
-        let mut html = self.data.clone();
+        let lookup = |name: &str| Self::lookup(variables, name);
+        let mut renderer = Renderer::new(None, &lookup, true);
+        let mut nodes = Vec::new();
+        renderer.render(&self.nodes, &Scope::default(), &mut nodes)?;
+        Ok(FwHTML { nodes, parent: None })
+    }
 
-        for comp_name in &self.used_components {
-            let comp = match components.get(comp_name) {
-                None => return Err(FwHTMLResolveError::MissingComponent(comp_name.clone())),
-                Some(comp) => comp,
-            };
-            html = html.replace(format!("{{{{ components/{comp_name} }}}}").as_str(), comp.data.as_str());
+    fn lookup<F>(variables: &HashMap<&String, F>, name: &str) -> Option<Result<Generated, FwHTMLResolveError>>
+    where
+        F: Fn() -> Result<Generated, builder::ValueGenerationError>,
+    {
+        variables.get(&name.to_string())
+            .map(|var| var().map_err(|err| FwHTMLResolveError::VariableError(name.to_string(), Box::new(err))))
+    }
+
+    /// Variables declared by this html and the components it includes.
+    ///
+    /// Declarations of variables that are set by component arguments or loop
+    /// items are left out.
+    pub fn contract(&self, components: &HashMap<String, FwHTML>) -> Vec<Declaration> {
+        let mut contract = Vec::new();
+        Self::collect_contract(&self.nodes, components, &mut Vec::new(), &mut Vec::new(), &mut contract);
+        contract
+    }
+
+    /// Add the declarations of [nodes] to [contract].
+    ///
+    /// [locals] are the names set by arguments and loops around [nodes],
+    /// [chain] the components being collected, which are skipped when they
+    /// include themselves.
+    fn collect_contract(nodes: &[ast::Node], components: &HashMap<String, FwHTML>, locals: &mut Vec<String>, chain: &mut Vec<String>, contract: &mut Vec<Declaration>) {
+        for node in nodes {
+            match node {
+                ast::Node::Declaration(declaration) => if !locals.contains(&declaration.name) {
+                    contract.push(declaration.clone());
+                },
+                ast::Node::Component(call) => {
+                    let Some(component) = components.get(&call.name) else {
+                        continue;
+                    };
+                    if chain.contains(&call.name) {
+                        continue;
+                    }
+                    let outer_locals = locals.len();
+                    locals.extend(call.args.iter().map(|(name, _)| name.clone()));
+                    chain.push(call.name.clone());
+                    Self::collect_contract(&component.nodes, components, locals, chain, contract);
+                    chain.pop();
+                    locals.truncate(outer_locals);
+                },
+                ast::Node::Block(block) => {
+                    let item = match &block.kind {
+                        ast::Kind::For { item, .. } => Some(item.clone()),
+                        ast::Kind::If(_) | ast::Kind::Named(_) => None,
+                    };
+                    let outer_locals = locals.len();
+                    locals.extend(item);
+                    Self::collect_contract(&block.body, components, locals, chain, contract);
+                    locals.truncate(outer_locals);
+                    if let Some(otherwise) = &block.otherwise {
+                        Self::collect_contract(otherwise, components, locals, chain, contract);
+                    }
+                },
+                ast::Node::Text(_) | ast::Node::Variable(_) => {},
+            }
         }
+    }
 
-        for var_name in &self.used_variables {
-            if let Some(var) = variables.get(var_name) {
-                let var = match var() {
-                    Err(err) => return Err(FwHTMLResolveError::VariableError(var_name.clone(), Box::new(err))),
-                    Ok(var) => var,
-                };
-                html = html.replace(format!("{{{{ {var_name} }}}}").as_str(), var.as_str());
+    /// Names of all variables used by tags, including those in blocks.
+    fn used_variables(&self) -> Vec<String> {
+        ast::variable_names(&self.nodes)
+    }
+
+    pub fn output(&self) -> String {
+        let mut html = String::new();
+        ast::write(&self.nodes, &mut html);
+        html
+    }
+}
+
+/// A variable a template or component expects, declared with
+/// `{{ requires <name>: <type> }}` or
+/// `{{ optional <name>: <type> = "<default>" }}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Declaration {
+    pub name: String,
+    pub var_type: VarType,
+    /// Value used when no step sets the variable, none for required
+    /// variables. Optional variables without default are blank.
+    pub default: Option<String>,
+    /// The tag as written in the html.
+    pub text: String,
+}
+
+/// Kind of value a [Declaration] accepts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VarType {
+    /// Text that gets escaped where it is inserted.
+    Text,
+    /// Markup like markdown, text files or indices.
+    Html,
+    /// A unix timestamp.
+    Timestamp,
+}
+
+impl VarType {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(VarType::Text),
+            "html" => Some(VarType::Html),
+            "timestamp" => Some(VarType::Timestamp),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            VarType::Text => "text",
+            VarType::Html => "html",
+            VarType::Timestamp => "timestamp",
+        }
+    }
+}
+
+/// Variables visible to a part of a template besides those of the build
+/// steps.
+#[derive(Clone, Default)]
+struct Scope {
+    /// Loop items and component arguments, later entries shadow earlier ones.
+    locals: Vec<(String, Generated)>,
+    /// Blocks are not expanded inside of blocks that are kept for later
+    /// steps.
+    keep_blocks: bool,
+}
+
+/// A single pass over the nodes of a [FwHTML].
+struct Renderer<'a> {
+    /// Components to insert and the dependencies to add them to, none after
+    /// the last step.
+    components: Option<(&'a HashMap<String, FwHTML>, &'a Dependencies)>,
+    lookup: &'a dyn Fn(&str) -> Option<Result<Generated, FwHTMLResolveError>>,
+    /// Whether unknown variables are undefined instead of kept.
+    finalize: bool,
+    /// Context at the end of the html rendered so far.
+    scanner: ContextScanner,
+    /// Components and variables whose content is being rendered, outermost
+    /// first.
+    chain: Vec<String>,
+}
+
+impl<'a> Renderer<'a> {
+    fn new(components: Option<(&'a HashMap<String, FwHTML>, &'a Dependencies)>, lookup: &'a dyn Fn(&str) -> Option<Result<Generated, FwHTMLResolveError>>, finalize: bool) -> Self {
+        Renderer {
+            components,
+            lookup,
+            finalize,
+            scanner: ContextScanner::new(),
+            chain: Vec::new(),
+        }
+    }
+
+    /// Render [nodes] as content of [link], failing if [link] is already
+    /// being rendered.
+    fn render_in(&mut self, link: String, nodes: &[ast::Node], scope: &Scope, rendered: &mut Vec<ast::Node>) -> Result<(), FwHTMLResolveError> {
+        if let Some(start) = self.chain.iter().position(|outer| *outer == link) {
+            let mut cycle = self.chain[start..].to_vec();
+            cycle.push(link);
+            return Err(FwHTMLResolveError::Cycle(cycle));
+        }
+        self.chain.push(link);
+        self.render(nodes, scope, rendered)?;
+        self.chain.pop();
+        Ok(())
+    }
+
+    /// Append [nodes] to [rendered] with everything [scope] and the build
+    /// steps define resolved.
+    fn render(&mut self, nodes: &[ast::Node], scope: &Scope, rendered: &mut Vec<ast::Node>) -> Result<(), FwHTMLResolveError> {
+        for node in nodes {
+            match node {
+                ast::Node::Text(text) => self.text(text, rendered),
+                ast::Node::Variable(tag) => self.variable(tag, scope, rendered)?,
+                ast::Node::Component(call) => self.component(call, scope, rendered)?,
+                ast::Node::Block(block) => self.block(block, scope, rendered)?,
+                ast::Node::Declaration(_) => {},
             }
         }
+        Ok(())
+    }
+
+    fn text(&mut self, text: &str, rendered: &mut Vec<ast::Node>) {
+        self.scanner.scan(text);
+        match rendered.last_mut() {
+            Some(ast::Node::Text(last)) => last.push_str(text),
+            _ => rendered.push(ast::Node::Text(text.to_string())),
+        }
+    }
+
+    /// Keep a tag for a later step.
+    fn keep(&mut self, node: ast::Node, rendered: &mut Vec<ast::Node>) {
+        let mut source = String::new();
+        ast::write(std::slice::from_ref(&node), &mut source);
+        self.scanner.scan(&source);
+        rendered.push(node);
+    }
+
+    fn value(&self, name: &str, scope: &Scope) -> Option<Result<Generated, FwHTMLResolveError>> {
+        match scope.locals.iter().rev().find(|(local, _)| local == name) {
+            Some((_, value)) => Some(Ok(value.clone())),
+            None => (self.lookup)(name),
+        }
+    }
 
-        let mut new = match Self::new(html) {
-            Err(err) => return Err(FwHTMLResolveError::GeneratesErrorInDocument(err)),
-            Ok(n) => n,
+    /// Insert the filtered value escaped for the context it lands in and
+    /// resolve the tags it contains.
+    fn variable(&mut self, tag: &ast::VariableTag, scope: &Scope, rendered: &mut Vec<ast::Node>) -> Result<(), FwHTMLResolveError> {
+        let is_local = scope.locals.iter().any(|(local, _)| *local == tag.name);
+        let value = match self.value(&tag.name, scope) {
+            Some(value) => value?,
+            None if self.finalize && tag.filters.iter().any(Filter::is_default) => Generated::Text(String::new()),
+            None => {
+                self.keep(ast::Node::Variable(tag.clone()), rendered);
+                return Ok(());
+            },
         };
-        while new.used_variables.iter().any(|e| variables.contains_key(e))
-            || new.used_components.iter().any(|e| components.contains_key(e)) {
-            let tmp = variables.clone();
-            //println!("{:?}", &new.used_variables.iter().filter(|e| tmp.contains_key(e.clone())));
-            //println!("{:?}", new.data);
-            new = new.resolved(&components, &variables)?;
-            new = match Self::new(new.data) {
-                Err(err) => return Err(FwHTMLResolveError::GeneratesErrorInDocument(err)),
-                Ok(n) => n,
-            };
+        let value = match tag.filters.iter().try_fold(value, |value, filter| filter.apply(value)) {
+            Err(err) => return Err(FwHTMLResolveError::FilterFailed(tag.text.clone(), err)),
+            Ok(value) => value,
+        };
+        let html = value.output(&self.scanner.context());
+        if self.finalize {
+            self.text(&html, rendered);
+            return Ok(());
+        }
+        let nodes = ast::parse(&html).map_err(FwHTMLResolveError::GeneratesErrorInDocument)?;
+        let inner = Scope { locals: Vec::new(), keep_blocks: scope.keep_blocks };
+        match is_local {
+            true => self.render(&nodes, &inner, rendered),
+            false => self.render_in(tag.name.clone(), &nodes, &inner, rendered),
         }
+    }
 
-        Ok(new)
+    /// Insert a component with its arguments, the rest of its variables is
+    /// resolved like at the include.
+    fn component(&mut self, call: &ast::ComponentCall, scope: &Scope, rendered: &mut Vec<ast::Node>) -> Result<(), FwHTMLResolveError> {
+        let Some((components, dependencies)) = self.components else {
+            self.keep(ast::Node::Component(call.clone()), rendered);
+            return Ok(());
+        };
+        dependencies.add(format!("components/{}", call.name));
+        let comp = match components.get(&call.name) {
+            None => return Err(FwHTMLResolveError::MissingComponent(call.name.clone())),
+            Some(comp) => comp,
+        };
+        let used_variables = comp.used_variables();
+        if let Some((arg_name, _)) = call.args.iter().find(|(arg_name, _)| !used_variables.contains(arg_name)) {
+            return Err(FwHTMLResolveError::UnknownComponentArgument(call.name.clone(), arg_name.clone()));
+        }
+        let args = Scope {
+            locals: call.args.iter()
+                .map(|(name, value)| (name.clone(), Generated::Text(value.clone())))
+                .collect(),
+            keep_blocks: true,
+        };
+        let mut instance = Vec::new();
+        Renderer::new(None, &|_| None, false).render(&comp.nodes, &args, &mut instance)?;
+        self.render_in(format!("components/{}", call.name), &instance, scope, rendered)
     }
 
-    pub fn output(&self) -> String {
-        // TODO: validate
-        self.data.clone()
+    /// Expand a block whose variable is known, keep the others for later
+    /// steps.
+    fn block(&mut self, block: &ast::Block, scope: &Scope, rendered: &mut Vec<ast::Node>) -> Result<(), FwHTMLResolveError> {
+        let variable = match &block.kind {
+            ast::Kind::Named(_) => return self.render(&block.body, scope, rendered),
+            ast::Kind::If(name) | ast::Kind::For { source: name, .. } => name,
+        };
+        // Locals are known inside of kept blocks too, unless a kept loop
+        // shadows them, which removes them from the scope
+        let is_local = scope.locals.iter().any(|(local, _)| local == variable);
+        let value = match scope.keep_blocks && !is_local {
+            true => None,
+            false => self.value(variable, scope),
+        };
+        match (&block.kind, value) {
+            (_, None) if scope.keep_blocks || !self.finalize => {
+                let locals = scope.locals.iter()
+                    .filter(|(local, _)| !matches!(&block.kind, ast::Kind::For { item, .. } if item == local))
+                    .cloned()
+                    .collect();
+                let inner = Scope { locals, keep_blocks: true };
+                let mut kept = ast::Block { kind: block.kind.clone(), body: Vec::new(), otherwise: None, offset: block.offset };
+                self.scanner.scan(&block.kind.open_tag());
+                self.render(&block.body, &inner, &mut kept.body)?;
+                if let Some(otherwise) = &block.otherwise {
+                    self.scanner.scan(ast::ELSE_TAG);
+                    self.render(otherwise, &inner, kept.otherwise.insert(Vec::new()))?;
+                }
+                self.scanner.scan(block.kind.close_tag());
+                rendered.push(ast::Node::Block(kept));
+            },
+            (ast::Kind::If(_), value) => {
+                let truthy = match value {
+                    None => false,
+                    Some(value) => value?.is_truthy(),
+                };
+                match (truthy, &block.otherwise) {
+                    (true, _) => self.render(&block.body, scope, rendered)?,
+                    (false, Some(otherwise)) => self.render(otherwise, scope, rendered)?,
+                    (false, None) => {},
+                }
+            },
+            (ast::Kind::Named(_), _) => unreachable!("named blocks are rendered as their content"),
+            (ast::Kind::For { source, .. }, None) => return Err(FwHTMLResolveError::UndefinedLoopSource(source.clone())),
+            (ast::Kind::For { item, source }, Some(value)) => {
+                let items = match value? {
+                    Generated::List(items) => items,
+                    _ => return Err(FwHTMLResolveError::NotAList(source.clone())),
+                };
+                for value in items {
+                    let mut inner = scope.clone();
+                    inner.locals.push((item.clone(), value));
+                    self.render(&block.body, &inner, rendered)?;
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+/// Make sure [html] has well-formed blocks and that all filters used in its
+/// variable tags exist and have valid arguments.
+pub fn check_tags(html: &str) -> Result<(), FwHTMLError> {
+    ast::parse(html).map(|_| ())
+}
+
+/// Class, tag and attribute names that are present in a html document.
+pub fn used_names(html: &str) -> UsedNames {
+    let document = Html::parse_document(html);
+    UsedNames {
+        classes: analyzer::classes(&document).into_iter().collect(),
+        tags: analyzer::tags(&document).into_iter().collect(),
+        attributes: analyzer::attributes(&document).into_iter().collect(),
+    }
+}
+
+/// `\{{` escapes and the tags of `{{ raw }}` blocks.
+static LITERAL_TAG_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"\\\{\{|\{\{ raw }}|\{\{ endraw }}")
+    .expect("Failed to compile literal regex"));
+
+/// Replace `\{{` escapes and the content of `{{ raw }}` blocks with text that
+/// shows up the same but is never resolved.
+///
+/// Browsers don't decode character references in `<script>` and `<style>`
+/// elements, so literals can't be used there.
+pub fn protect_literals(html: &str) -> Result<String, FwHTMLError> {
+    let mut protected = String::with_capacity(html.len());
+    let mut raw_start = None;
+    let mut last_end = 0;
+    let mut scanner = ContextScanner::new();
+    for tag in LITERAL_TAG_REGEX.find_iter(html) {
+        if raw_start.is_none() {
+            scanner.scan(&html[last_end..tag.start()]);
+            if scanner.context() == html::Context::RawText {
+                return Err(FwHTMLError::InvalidBlock(tag.as_str().to_string(), "can't be used in `<script>` or `<style>` elements"));
+            }
+        }
+        match (tag.as_str(), raw_start) {
+            ("{{ raw }}", None) => {
+                protected += &html[last_end..tag.start()];
+                raw_start = Some(tag.start());
+            },
+            ("{{ endraw }}", None) => return Err(FwHTMLError::InvalidBlock(tag.as_str().to_string(), "has no opening block")),
+            ("{{ endraw }}", Some(start)) => {
+                let raw = &html[start + "{{ raw }}".len()..tag.start()];
+                let mut scanned = 0;
+                for (braces, _) in raw.match_indices("{{") {
+                    scanner.scan(&raw[scanned..braces]);
+                    if scanner.context() == html::Context::RawText {
+                        return Err(FwHTMLError::InvalidBlock(String::from("{{ raw }}"), "can't be used in `<script>` or `<style>` elements"));
+                    }
+                    scanned = braces;
+                }
+                scanner.scan(&raw[scanned..]);
+                protected += &literal_braces(raw);
+                raw_start = None;
+            },
+            (_, Some(_)) => continue,
+            (escape, None) => {
+                protected += &html[last_end..tag.start()];
+                protected += &literal_braces(&escape[1..]);
+            },
+        }
+        last_end = tag.end();
+    }
+    if raw_start.is_some() {
+        return Err(FwHTMLError::InvalidBlock(String::from("{{ raw }}"), "is never closed"));
+    }
+    protected += &html[last_end..];
+    Ok(protected)
+}
+
+/// Write the `{` of `{{` as character reference so no tag starts in [text].
+pub fn literal_braces(text: &str) -> String {
+    let mut literal = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) => literal += "&#123;",
+            (c, _) => literal.push(c),
+        }
     }
+    literal
 }
 
 #[derive(Debug)]
 pub enum FwHTMLError {
-    DocumentContainsErrors(Vec<Cow<'static, str>>),
+    DocumentContainsErrors(Vec<HtmlError>),
+    /// Variable tag, err
+    InvalidFilter(String, FilterParseError),
+    /// Block or literal tag, what is wrong with it
+    InvalidBlock(String, &'static str),
     /// Doesn't start with "<!DOCTYPE html>"
     NotATemplate,
+    /// Declaration tag, what is wrong with it
+    InvalidDeclaration(String, &'static str),
+    /// Text of a template extending another one that isn't in a named block
+    ContentOutsideOfBlocks(String),
 }
 
 #[derive(Debug)]
 pub enum FwHTMLResolveError {
     MissingComponent(String),
+    /// Component name, name of the argument the component doesn't use
+    UnknownComponentArgument(String, String),
     VariableError(String, Box<ValueGenerationError>),
+    /// Variable a `{{ for }}` block iterates that isn't defined after the
+    /// last step.
+    UndefinedLoopSource(String),
+    /// Variable a `{{ for }}` block iterates that isn't a list.
+    NotAList(String),
+    /// Variable tag, err
+    FilterFailed(String, FilterError),
+    /// Components as `components/<name>` and variables that include each
+    /// other, starting and ending with the same one.
+    Cycle(Vec<String>),
     GeneratesErrorInDocument(FwHTMLError),
 }
 
 mod analyzer {
     use ego_tree::iter::Edge;
     use itertools::Itertools;
-    use once_cell::sync::Lazy;
-    use regex::Regex;
 
-    pub fn classes(html: scraper::Html) -> Vec<String> {
+    pub fn classes(html: &scraper::Html) -> Vec<String> {
         let mut classes = Vec::new();
         for node in html.tree.root().traverse() {
             if let Edge::Open(node) = node {
@@ -243,24 +799,641 @@ mod analyzer {
         classes
     }
 
-    static VAR_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{\{ ([\w\-_]*) }}")
-        .expect("Failed to compile classes regex"));
-    pub fn used_variables(html: &str) -> Vec<String> {
-        extract_regex_captures(&VAR_REGEX, html)
+    pub fn tags(html: &scraper::Html) -> Vec<String> {
+        html.tree.root().descendants()
+            .filter_map(|node| node.value().as_element())
+            .map(|element| element.name().to_string())
+            .sorted().dedup()
+            .collect()
     }
 
-    static COMP_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{\{ components/([\w\-_]*) }}")
-        .expect("Failed to compile classes regex"));
-    pub fn used_components(html: &str) -> Vec<String> {
-        extract_regex_captures(&COMP_REGEX, html)
+    pub fn attributes(html: &scraper::Html) -> Vec<String> {
+        html.tree.root().descendants()
+            .filter_map(|node| node.value().as_element())
+            .flat_map(|element| element.attrs().map(|(name, _)| name.to_string()))
+            .sorted().dedup()
+            .collect()
     }
+}
 
-    fn extract_regex_captures(regex: &Lazy<Regex, fn() -> Regex>, haystack: &str) -> Vec<String> {
-        regex.captures_iter(haystack)
-            .map(|e| e.get(1))
-            .filter(|e| e.is_some())
-            .map(|e| e.unwrap().as_str().to_string())
-            .sorted().dedup()
-            .collect::<Vec<String>>()
+mod ast {
+    use std::borrow::Cow;
+    use std::collections::{HashMap, HashSet};
+
+    use once_cell::sync::Lazy;
+    use regex::Regex;
+
+    use crate::filters::Filter;
+    use crate::ir::{Declaration, FwHTMLError, VarType};
+
+    static EXTENDS_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\s*\{\{ extends ([\w\-_./]+) }}")
+        .expect("Failed to compile extends regex"));
+
+    /// Tags of named blocks and declarations.
+    static SILENT_TAG_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\{\{ (?:block [\w\-_]+|endblock|(?:requires|optional) [\w\-_]+: \w+(?: = "(?:[^"\\]|\\.)*")?) }}"#)
+        .expect("Failed to compile silent tag regex"));
+
+    /// Tags without variable that can't be used as variable names.
+    pub const KEYWORDS: [&str; 6] = ["else", "endif", "endfor", "endblock", "raw", "endraw"];
+
+    pub const ELSE_TAG: &str = "{{ else }}";
+
+    static VAR_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\{\{ ([\w\-_]*)((?:\s*\|\s*[\w\-_]+(?:\((?:"(?:[^"\\]|\\.)*"|[^)"])*\))?)*) }}"#)
+        .expect("Failed to compile variable regex"));
+
+    static COMP_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\{\{ components/([\w\-_/]*)((?: [\w\-_]+="(?:[^"\\]|\\.)*")*) }}"#)
+        .expect("Failed to compile component regex"));
+
+    static ARG_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"([\w\-_]+)="((?:[^"\\]|\\.)*)""#)
+        .expect("Failed to compile component argument regex"));
+
+    static DECLARATION_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\{\{ (requires|optional) ([\w\-_]+): (\w+)(?: = "((?:[^"\\]|\\.)*)")? }}"#)
+        .expect("Failed to compile declaration regex"));
+
+    static BLOCK_REGEX: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{\{ (?:if ([\w\-_]+)|for ([\w\-_]+) in ([\w\-_]+)|(else)|(endif)|(endfor)|block ([\w\-_]+)|(endblock)) }}")
+        .expect("Failed to compile block regex"));
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Node {
+        Text(String),
+        Variable(VariableTag),
+        Component(ComponentCall),
+        Block(Block),
+        /// Renders to nothing.
+        Declaration(Declaration),
+    }
+
+    /// A `{{ name }}` or `{{ name | filter }}` tag.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct VariableTag {
+        pub name: String,
+        pub filters: Vec<Filter>,
+        /// The tag as written in the html.
+        pub text: String,
+        /// Byte offset of the tag in the parsed html.
+        pub offset: usize,
+    }
+
+    /// A `{{ components/<name> key="value" }}` include.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct ComponentCall {
+        pub name: String,
+        /// Argument names and their unescaped values in call order.
+        pub args: Vec<(String, String)>,
+        /// The include as written in the html.
+        pub text: String,
+        /// Byte offset of the include in the parsed html.
+        pub offset: usize,
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Kind {
+        /// `{{ if <variable> }}`
+        If(String),
+        /// `{{ for <item> in <source> }}`
+        For {
+            item: String,
+            source: String,
+        },
+        /// `{{ block <name> }}` that templates extending the document can
+        /// override.
+        Named(String),
+    }
+
+    impl Kind {
+        pub fn open_tag(&self) -> String {
+            match self {
+                Kind::If(name) => format!("{{{{ if {name} }}}}"),
+                Kind::For { item, source } => format!("{{{{ for {item} in {source} }}}}"),
+                Kind::Named(name) => format!("{{{{ block {name} }}}}"),
+            }
+        }
+
+        pub fn close_tag(&self) -> &'static str {
+            match self {
+                Kind::If(_) => "{{ endif }}",
+                Kind::For { .. } => "{{ endfor }}",
+                Kind::Named(_) => "{{ endblock }}",
+            }
+        }
+    }
+
+    /// A `{{ if }}`, `{{ for }}` or `{{ block }}` block.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Block {
+        pub kind: Kind,
+        /// Content up to the `{{ else }}` or end tag.
+        pub body: Vec<Node>,
+        /// Content between `{{ else }}` and `{{ endif }}`.
+        pub otherwise: Option<Vec<Node>>,
+        /// Byte offset of the opening tag in the parsed html.
+        pub offset: usize,
+    }
+
+    enum Tag {
+        Variable(VariableTag),
+        Component(ComponentCall),
+        Declaration(Declaration),
+        Open(Kind),
+        Else,
+        EndIf,
+        EndFor,
+        EndBlock,
+    }
+
+    /// Parse [html] into text, tags and the blocks they form.
+    pub fn parse(html: &str) -> Result<Vec<Node>, FwHTMLError> {
+        let mut root = Vec::new();
+        // Blocks that aren't closed yet with their opening tag
+        let mut open: Vec<(Block, &str)> = Vec::new();
+        let mut last_end = 0;
+        for (range, tag) in tags(html)? {
+            let text = &html[range.clone()];
+            let invalid = |problem| Err(FwHTMLError::InvalidBlock(text.to_string(), problem));
+            let nodes = match open.last_mut() {
+                None => &mut root,
+                Some((block, _)) => block.otherwise.as_mut().unwrap_or(&mut block.body),
+            };
+            if range.start > last_end {
+                nodes.push(Node::Text(html[last_end..range.start].to_string()));
+            }
+            last_end = range.end;
+            match tag {
+                Tag::Variable(tag) => nodes.push(Node::Variable(tag)),
+                Tag::Component(call) => nodes.push(Node::Component(call)),
+                Tag::Declaration(declaration) => nodes.push(Node::Declaration(declaration)),
+                Tag::Open(kind) => open.push((Block { kind, body: Vec::new(), otherwise: None, offset: range.start }, text)),
+                Tag::Else => match open.last_mut() {
+                    None => return invalid("has no opening block"),
+                    Some((Block { kind: Kind::For { .. } | Kind::Named(_), .. }, _)) => return invalid("can only be used in `{{ if }}` blocks"),
+                    Some((Block { otherwise: Some(_), .. }, _)) => return invalid("is the second `{{ else }}` of its block"),
+                    Some((block, _)) => block.otherwise = Some(Vec::new()),
+                },
+                Tag::EndIf | Tag::EndFor | Tag::EndBlock => {
+                    let block = match open.pop() {
+                        None => return invalid("has no opening block"),
+                        Some((block, _)) => block,
+                    };
+                    match (&block.kind, tag) {
+                        (Kind::If(_), Tag::EndIf) | (Kind::For { .. }, Tag::EndFor) | (Kind::Named(_), Tag::EndBlock) => {},
+                        (Kind::If(_), _) => return invalid("closes an `{{ if }}` block, use `{{ endif }}`"),
+                        (Kind::For { .. }, _) => return invalid("closes a `{{ for }}` block, use `{{ endfor }}`"),
+                        (Kind::Named(_), _) => return invalid("closes a `{{ block }}`, use `{{ endblock }}`"),
+                    }
+                    match open.last_mut() {
+                        None => &mut root,
+                        Some((parent, _)) => parent.otherwise.as_mut().unwrap_or(&mut parent.body),
+                    }.push(Node::Block(block));
+                },
+            }
+        }
+        if let Some((_, tag)) = open.pop() {
+            return Err(FwHTMLError::InvalidBlock(tag.to_string(), "is never closed"));
+        }
+        if last_end < html.len() {
+            root.push(Node::Text(html[last_end..].to_string()));
+        }
+        Ok(root)
+    }
+
+    /// All tags of [html] in document order, tags inside of other tags are
+    /// part of their text.
+    fn tags(html: &str) -> Result<Vec<(std::ops::Range<usize>, Tag)>, FwHTMLError> {
+        let mut tags = Vec::new();
+        for tag in VAR_REGEX.captures_iter(html) {
+            if KEYWORDS.contains(&&tag[1]) {
+                continue;
+            }
+            let whole = tag.get(0).unwrap();
+            let filters = match Filter::parse_pipeline(&tag[2]) {
+                Err(err) => return Err(FwHTMLError::InvalidFilter(whole.as_str().to_string(), err)),
+                Ok(filters) => filters,
+            };
+            tags.push((whole.range(), Tag::Variable(VariableTag {
+                name: tag[1].to_string(),
+                filters,
+                text: whole.as_str().to_string(),
+                offset: whole.start(),
+            })));
+        }
+        for call in COMP_REGEX.captures_iter(html) {
+            let whole = call.get(0).unwrap();
+            tags.push((whole.range(), Tag::Component(ComponentCall {
+                name: call[1].to_string(),
+                args: ARG_REGEX.captures_iter(&call[2])
+                    .map(|arg| (arg[1].to_string(), unescape(&arg[2])))
+                    .collect(),
+                text: whole.as_str().to_string(),
+                offset: whole.start(),
+            })));
+        }
+        for tag in DECLARATION_REGEX.captures_iter(html) {
+            let whole = tag.get(0).unwrap();
+            let invalid = |problem| Err(FwHTMLError::InvalidDeclaration(whole.as_str().to_string(), problem));
+            let Some(var_type) = VarType::parse(&tag[3]) else {
+                return invalid("has an unknown type, use `text`, `html` or `timestamp`");
+            };
+            let default = match (&tag[1], tag.get(4)) {
+                ("requires", Some(_)) => return invalid("can't have a default, use `optional`"),
+                ("requires", None) => None,
+                (_, Some(default)) => Some(unescape(default.as_str())),
+                (_, None) => Some(String::new()),
+            };
+            if var_type == VarType::Timestamp && default.as_ref().is_some_and(|d| !d.is_empty() && d.parse::<u64>().is_err()) {
+                return invalid("needs a unix timestamp in seconds as default");
+            }
+            tags.push((whole.range(), Tag::Declaration(Declaration {
+                name: tag[2].to_string(),
+                var_type,
+                default,
+                text: whole.as_str().to_string(),
+            })));
+        }
+        for tag in BLOCK_REGEX.captures_iter(html) {
+            let kind = if let Some(name) = tag.get(1) {
+                Tag::Open(Kind::If(name.as_str().to_string()))
+            } else if let Some((item, source)) = tag.get(2).zip(tag.get(3)) {
+                Tag::Open(Kind::For { item: item.as_str().to_string(), source: source.as_str().to_string() })
+            } else if tag.get(4).is_some() {
+                Tag::Else
+            } else if tag.get(5).is_some() {
+                Tag::EndIf
+            } else if tag.get(6).is_some() {
+                Tag::EndFor
+            } else if let Some(name) = tag.get(7) {
+                Tag::Open(Kind::Named(name.as_str().to_string()))
+            } else {
+                Tag::EndBlock
+            };
+            tags.push((tag.get(0).unwrap().range(), kind));
+        }
+        tags.sort_by_key(|(range, _)| range.start);
+        let mut last_end = 0;
+        tags.retain(|(range, _)| {
+            let outside = range.start >= last_end;
+            if outside {
+                last_end = range.end;
+            }
+            outside
+        });
+        Ok(tags)
+    }
+
+    /// Argument values are quoted with `"`, a `\"` or `\\` inside a value
+    /// stands for a literal `"` or `\`.
+    fn unescape(value: &str) -> String {
+        let mut unescaped = String::with_capacity(value.len());
+        let mut chars = value.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => unescaped.extend(chars.next()),
+                c => unescaped.push(c),
+            }
+        }
+        unescaped
+    }
+
+    /// Append the html [nodes] were parsed from, or render to, to [html].
+    ///
+    /// Named blocks are replaced by their content.
+    pub fn write(nodes: &[Node], html: &mut String) {
+        for node in nodes {
+            match node {
+                Node::Text(text) => html.push_str(text),
+                Node::Variable(tag) => html.push_str(&tag.text),
+                Node::Component(call) => html.push_str(&call.text),
+                Node::Declaration(declaration) => html.push_str(&declaration.text),
+                Node::Block(Block { kind: Kind::Named(_), body, .. }) => write(body, html),
+                Node::Block(block) => {
+                    html.push_str(&block.kind.open_tag());
+                    write(&block.body, html);
+                    if let Some(otherwise) = &block.otherwise {
+                        html.push_str(ELSE_TAG);
+                        write(otherwise, html);
+                    }
+                    html.push_str(block.kind.close_tag());
+                },
+            }
+        }
+    }
+
+    /// Name of the template [html] extends with a leading
+    /// `{{ extends <template> }}` and the html after that tag.
+    pub fn extends(html: &str) -> Option<(&str, &str)> {
+        let extends = EXTENDS_REGEX.captures(html)?;
+        Some((extends.get(1).unwrap().as_str(), &html[extends.get(0).unwrap().end()..]))
+    }
+
+    /// [html] without the tags of named blocks and declarations, which would
+    /// be text where they are placed, e.g. in the `<head>`.
+    pub fn without_silent_tags(html: &str) -> Cow<'_, str> {
+        SILENT_TAG_REGEX.replace_all(html, "")
+    }
+
+    /// Trimmed text of the first top level node that is neither a named
+    /// block, a declaration nor whitespace.
+    pub fn content_outside_of_named_blocks(nodes: &[Node]) -> Option<String> {
+        nodes.iter()
+            .filter(|node| !matches!(node, Node::Block(Block { kind: Kind::Named(_), .. }) | Node::Declaration(_)))
+            .map(|node| {
+                let mut html = String::new();
+                write(std::slice::from_ref(node), &mut html);
+                html.trim().to_string()
+            })
+            .find(|content| !content.is_empty())
+    }
+
+    /// Content of the top level named blocks of [nodes] by their name.
+    pub fn named_blocks(nodes: &[Node]) -> HashMap<&str, &[Node]> {
+        nodes.iter()
+            .filter_map(|node| match node {
+                Node::Block(Block { kind: Kind::Named(name), body, .. }) => Some((name.as_str(), body.as_slice())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// [nodes] with the content of named blocks replaced by the content in
+    /// [overrides], adding the names of replaced blocks to [overridden].
+    pub fn overridden(nodes: &[Node], overrides: &HashMap<&str, &[Node]>, overridden: &mut HashSet<String>) -> Vec<Node> {
+        nodes.iter()
+            .map(|node| match node {
+                Node::Block(block) => {
+                    let content = match &block.kind {
+                        Kind::Named(name) => overrides.get(name.as_str()).map(|content| (name, content)),
+                        _ => None,
+                    };
+                    let mut block = block.clone();
+                    match content {
+                        Some((name, content)) => {
+                            overridden.insert(name.clone());
+                            block.body = content.to_vec();
+                        },
+                        None => {
+                            block.body = self::overridden(&block.body, overrides, overridden);
+                            block.otherwise = block.otherwise.map(|otherwise| self::overridden(&otherwise, overrides, overridden));
+                        },
+                    }
+                    Node::Block(block)
+                },
+                node => node.clone(),
+            })
+            .collect()
+    }
+
+    /// Names of all variables in tags of [nodes] and their blocks, including
+    /// the conditions and sources of blocks.
+    pub fn variable_names(nodes: &[Node]) -> Vec<String> {
+        let mut names = Vec::new();
+        for node in nodes {
+            match node {
+                Node::Variable(tag) => names.push(tag.name.clone()),
+                Node::Block(block) => {
+                    match &block.kind {
+                        Kind::If(name) | Kind::For { source: name, .. } => names.push(name.clone()),
+                        Kind::Named(_) => {},
+                    }
+                    names.extend(variable_names(&block.body));
+                    names.extend(block.otherwise.iter().flat_map(|otherwise| variable_names(otherwise)));
+                },
+                Node::Text(_) | Node::Component(_) | Node::Declaration(_) => {},
+            }
+        }
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::build_cache::{BuildCache, Dependencies};
+    use crate::builder::{Generated, Value, ValueGenerationError};
+    use crate::filters::Filter;
+    use crate::fs_tree::FsTree;
+    use crate::ir::{ast, FwHTML, FwHTMLError, FwHTMLResolveError, SourceFormatError, VarType, WebsiteConf, IR};
+    use crate::source_dir::SourceDir;
+
+    fn resolve(html: &str, components: &[(&str, &str)]) -> Result<String, FwHTMLResolveError> {
+        let components = components.iter()
+            .map(|(name, html)| (name.to_string(), FwHTML::component(html.to_string()).unwrap()))
+            .collect();
+        let (title, tags, empty, quote) = (String::from("title"), String::from("tags"), String::from("empty"), String::from("quote"));
+        let variables = HashMap::from([
+            (&title, Generated::Text(String::from("Global"))),
+            (&tags, Generated::List(vec![Generated::Text(String::from("a")), Generated::Text(String::from("b"))])),
+            (&empty, Generated::Text(String::new())),
+            (&quote, Generated::Text(String::from("<b>\"a&b\"</b>"))),
+        ]);
+        let variables: HashMap<_, _> = variables.iter()
+            .map(|(name, value)| (*name, || Ok::<_, ValueGenerationError>(value.clone())))
+            .collect();
+        FwHTML::new(html.to_string()).unwrap()
+            .resolved(&components, &variables, &Dependencies::new())?
+            .finalized(&variables)
+            .map(|html| html.output())
+    }
+
+    #[test]
+    fn scopes_component_arguments_to_instance() {
+        let components = [("video", "<video src=\"{{ src }}\" title=\"{{ title }}\"></video>")];
+        let html = resolve(r#"<p>{{ components/video src="a.mp4" }}{{ components/video src="b.mp4" title="Say \"hi\"" }}</p>"#, &components).unwrap();
+        assert!(html.contains(r#"<video src="a.mp4" title="Global"></video><video src="b.mp4" title="Say &quot;hi&quot;"></video>"#));
+
+        let err = resolve(r#"{{ components/video source="a.mp4" }}"#, &components).unwrap_err();
+        assert!(matches!(err, FwHTMLResolveError::UnknownComponentArgument(component, arg) if component == "video" && arg == "source"));
+    }
+
+    #[test]
+    fn evaluates_blocks_with_component_arguments() {
+        let components = [
+            ("cap", "<div>{{ if caption }}<p>{{ caption }}</p>{{ else }}none{{ endif }}</div>"),
+            ("flag", "<i>{{ if flag }}on{{ else }}off{{ endif }}</i>"),
+            ("shadow", "<ul>{{ for caption in tags }}{{ if caption }}<li>{{ caption }}</li>{{ endif }}{{ endfor }}</ul>"),
+        ];
+        let html = resolve(r#"{{ components/cap caption="x" }}{{ components/cap }}"#, &components).unwrap();
+        assert!(html.contains("<div><p>x</p></div><div>none</div>"));
+        let html = resolve(r#"{{ components/flag flag="yes" }}{{ components/flag flag="" }}"#, &components).unwrap();
+        assert!(html.contains("<i>on</i><i>off</i>"));
+        let html = resolve(r#"{{ components/shadow caption="x" }}"#, &components).unwrap();
+        assert!(html.contains("<ul><li>a</li><li>b</li></ul>"));
+    }
+
+    #[test]
+    fn expands_blocks() {
+        let components = [("tag", "<li>{{ if tag }}{{ tag }}{{ else }}none{{ endif }}</li>")];
+        let html = resolve("<ul>{{ for tag in tags }}{{ components/tag }}{{ endfor }}</ul>", &components).unwrap();
+        assert!(html.contains("<ul><li>a</li><li>b</li></ul>"));
+        let html = resolve("<p>{{ if empty }}{{ title }}{{ else }}{{ if missing }}?{{ else }}!{{ endif }}{{ endif }}</p>", &[]).unwrap();
+        assert!(html.contains("<p>!</p>"));
+
+        assert!(matches!(resolve("{{ for x in missing }}{{ x }}{{ endfor }}", &[]), Err(FwHTMLResolveError::UndefinedLoopSource(name)) if name == "missing"));
+        assert!(matches!(resolve("{{ for x in title }}{{ x }}{{ endfor }}", &[]), Err(FwHTMLResolveError::NotAList(name)) if name == "title"));
+        assert!(matches!(FwHTML::component(String::from("{{ if a }}{{ endfor }}")), Err(FwHTMLError::InvalidBlock(..))));
+        assert!(matches!(FwHTML::component(String::from("{{ if a }}")), Err(FwHTMLError::InvalidBlock(..))));
+    }
+
+    #[test]
+    fn filters_variables() {
+        let html = resolve(r#"<p>{{ title | upper }} {{ missing | default("none") }} {{ tags | json }}</p>"#, &[]).unwrap();
+        assert!(html.contains(r#"<p>GLOBAL none ["a","b"]</p>"#));
+        assert!(matches!(FwHTML::component(String::from("{{ title | shout }}")), Err(FwHTMLError::InvalidFilter(..))));
+    }
+
+    #[test]
+    fn parses_tags_into_nodes() {
+        let html = r#"<p>{{ title | upper }}{{ if a }}{{ components/x k="v" }}{{ else }}-{{ endif }}</p>"#;
+        let nodes = ast::parse(html).unwrap();
+        assert_eq!(nodes.len(), 4);
+        assert!(matches!(&nodes[1], ast::Node::Variable(tag) if tag.name == "title" && tag.filters == vec![Filter::Upper] && tag.offset == 3));
+        let ast::Node::Block(block) = &nodes[2] else { panic!("{nodes:?}") };
+        assert_eq!(block.kind, ast::Kind::If(String::from("a")));
+        assert_eq!(block.offset, 22);
+        assert!(matches!(&block.body[..], [ast::Node::Component(call)] if call.name == "x" && call.args == vec![(String::from("k"), String::from("v"))]));
+        assert_eq!(block.otherwise, Some(vec![ast::Node::Text(String::from("-"))]));
+
+        let resolved = FwHTML::new(html.to_string()).unwrap()
+            .resolved(&HashMap::from([(String::from("x"), FwHTML::component(String::from("<b>{{ k }}</b>")).unwrap())]), &HashMap::<&String, fn() -> _>::new(), &Dependencies::new())
+            .unwrap();
+        assert_eq!(resolved.output(), r#"<p>{{ title | upper }}{{ if a }}<b>v</b>{{ else }}-{{ endif }}</p>"#);
+    }
+
+    #[test]
+    fn detects_cycles() {
+        let components = [("a", "<p>{{ components/b }}</p>"), ("b", "<p>{{ components/a }}</p>")];
+        let err = resolve("{{ components/a }}", &components).unwrap_err();
+        assert!(matches!(err, FwHTMLResolveError::Cycle(chain) if chain == ["components/a", "components/b", "components/a"]));
+
+        let (description, meta) = (String::from("description"), String::from("meta-description"));
+        let variables: HashMap<_, fn() -> _> = HashMap::from([
+            (&description, (|| Ok(Generated::Text(String::from("{{ meta-description }}")))) as fn() -> _),
+            (&meta, || Ok(Generated::Text(String::from("<{{ description }}>")))),
+        ]);
+        let err = FwHTML::new(String::from("<p>{{ description }}</p>")).unwrap()
+            .resolved(&HashMap::new(), &variables, &Dependencies::new())
+            .unwrap_err();
+        assert!(matches!(err, FwHTMLResolveError::Cycle(chain) if chain == ["description", "meta-description", "description"]));
+
+        let components = [("tag", "<li>{{ tag }}</li>")];
+        let html = resolve("<ul>{{ for tag in tags }}{{ components/tag }}{{ components/tag }}{{ endfor }}</ul>", &components).unwrap();
+        assert!(html.contains("<ul><li>a</li><li>a</li><li>b</li><li>b</li></ul>"));
+    }
+
+    fn templates(templates: &[(&str, &str)]) -> Result<HashMap<String, FwHTML>, SourceFormatError> {
+        IR::load_templates(templates.iter()
+            .map(|(name, html)| (name.to_string(), html.to_string()))
+            .collect())
+    }
+
+    #[test]
+    fn composes_extended_templates() {
+        let base = ("base.html", "<!DOCTYPE html>\n<html><head><title>{{ title }}</title>{{ block head }}{{ endblock }}</head><body>{{ block main }}<p>Empty</p>{{ endblock }}</body></html>");
+        let blog = ("blog.html", "{{ extends base.html }}\n{{ block head }}<link rel=\"alternate\" href=\"/rss.xml\">{{ endblock }}\n");
+        let post = ("post.html", "{{ extends blog.html }}\n{{ block main }}<article>{{ block text }}{{ endblock }}</article>{{ endblock }}");
+        let loaded = templates(&[base, blog, post]).unwrap();
+        assert_eq!(loaded["base.html"].output(), "<!DOCTYPE html>\n<html><head><title>{{ title }}</title></head><body><p>Empty</p></body></html>");
+        assert_eq!(loaded["blog.html"].output(), "<!DOCTYPE html>\n<html><head><title>{{ title }}</title><link rel=\"alternate\" href=\"/rss.xml\"></head><body><p>Empty</p></body></html>");
+        assert_eq!(loaded["post.html"].output(), "<!DOCTYPE html>\n<html><head><title>{{ title }}</title><link rel=\"alternate\" href=\"/rss.xml\"></head><body><article></article></body></html>");
+
+        let err = templates(&[base, ("a.html", "{{ extends b.html }}"), ("b.html", "{{ extends a.html }}")]).unwrap_err();
+        assert!(matches!(err, SourceFormatError::CyclicTemplates(chain) if chain.len() == 3 && chain[0] == chain[2]));
+        let err = templates(&[("a.html", "{{ extends missing.html }}")]).unwrap_err();
+        assert!(matches!(err, SourceFormatError::MissingParentTemplate(file, parent) if file == "templates/a.html" && parent == "missing.html"));
+        let err = templates(&[base, ("a.html", "{{ extends base.html }}{{ block footer }}{{ endblock }}")]).unwrap_err();
+        assert!(matches!(err, SourceFormatError::UnknownTemplateBlock(_, block) if block == "footer"));
+        let err = templates(&[base, ("a.html", "{{ extends base.html }}<p>Lost</p>")]).unwrap_err();
+        assert!(matches!(err, SourceFormatError::InvalidTemplateHTML(_, FwHTMLError::ContentOutsideOfBlocks(content)) if content == "<p>Lost</p>"));
+    }
+
+    #[test]
+    fn keeps_literals() {
+        let components = [("code", r"<p>\{{ title }} {{ raw }}{{ title }} {{ if x }}{{ endraw }} {{ title }}</p>")];
+        let html = resolve("{{ components/code }}", &components).unwrap();
+        assert!(html.contains("<p>&#123;{ title }} &#123;{ title }} &#123;{ if x }} Global</p>"), "{html}");
+
+        assert!(matches!(FwHTML::component(String::from("{{ raw }}{{ title }}")), Err(FwHTMLError::InvalidBlock(..))));
+        assert!(matches!(FwHTML::component(String::from("{{ endraw }}")), Err(FwHTMLError::InvalidBlock(..))));
+        assert!(matches!(FwHTML::component(String::from("<script>{{ raw }}{{ x }}{{ endraw }}</script>")), Err(FwHTMLError::InvalidBlock(tag, _)) if tag == "{{ raw }}"));
+        assert!(matches!(FwHTML::component(String::from(r"<style>\{{ x }}</style>")), Err(FwHTMLError::InvalidBlock(..))));
+        assert!(FwHTML::component(String::from("{{ raw }}<style>{{ x }}</style>{{ endraw }}")).is_err());
+        assert!(FwHTML::component(String::from("<script></script>{{ raw }}<style></style>{{ x }}{{ endraw }}<p>\\{{ x }}</p>")).is_ok());
+    }
+
+    #[test]
+    fn escapes_values_by_context() {
+        let html = resolve(r#"<p title="{{ quote }}">{{ quote }} {{ quote | raw }}</p><a href="{{ quote }}">"#, &[]).unwrap();
+        assert!(html.contains(r#"<p title="&lt;b&gt;&quot;a&amp;b&quot;&lt;/b&gt;">&lt;b&gt;"a&amp;b"&lt;/b&gt; <b>"a&b"</b></p>"#), "{html}");
+        assert!(html.contains(r#"<a href="%3Cb%3E%22a&amp;b%22%3C/b%3E">"#), "{html}");
+    }
+
+    #[test]
+    fn collects_contracts() {
+        let components = [
+            ("video", "{{ requires src: text }}{{ optional caption: html = \"<i>none</i>\" }}<video src=\"{{ src }}\"></video>{{ caption }}"),
+            ("tag", "{{ requires tag: text }}<li>{{ tag }}</li>"),
+        ].iter()
+            .map(|(name, html)| (name.to_string(), FwHTML::component(html.to_string()).unwrap()))
+            .collect();
+        let html = FwHTML::new(String::from("{{ requires time: timestamp }}{{ components/video src=\"a.mp4\" }}{{ for tag in tags }}{{ components/tag }}{{ endfor }}")).unwrap();
+        let contract = html.contract(&components).into_iter()
+            .map(|declaration| (declaration.name, declaration.var_type, declaration.default))
+            .collect::<Vec<_>>();
+        assert_eq!(contract, vec![
+            (String::from("time"), VarType::Timestamp, None),
+            (String::from("caption"), VarType::Html, Some(String::from("<i>none</i>"))),
+        ]);
+        assert_eq!(resolve("<p>{{ optional title: text }}{{ title }}</p>", &[]).unwrap(), "<p>Global</p>");
+
+        assert!(matches!(FwHTML::component(String::from("{{ requires a: number }}")), Err(FwHTMLError::InvalidDeclaration(..))));
+        assert!(matches!(FwHTML::component(String::from("{{ requires a: text = \"b\" }}")), Err(FwHTMLError::InvalidDeclaration(..))));
+        assert!(matches!(FwHTML::component(String::from("{{ optional a: timestamp = \"today\" }}")), Err(FwHTMLError::InvalidDeclaration(..))));
+
+        let base = ("base.html", "<!DOCTYPE html>\n<html><head>{{ requires title: text }}<title>{{ title }}</title></head><body>{{ block main }}{{ endblock }}</body></html>");
+        let post = ("post.html", "{{ extends base.html }}\n{{ requires text: html }}\n{{ block main }}{{ text }}{{ endblock }}");
+        let loaded = templates(&[base, post]).unwrap();
+        let names = loaded["post.html"].contract(&HashMap::new()).into_iter()
+            .map(|declaration| declaration.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec![String::from("title"), String::from("text")]);
+    }
+
+    #[test]
+    fn loads_global_variables() {
+        let mut config: WebsiteConf = serde_yml::from_str("url: example.com/\nvariables:\n  author: derdilla\n  site-url: https://example.org\n").unwrap();
+        assert_eq!(config.site_url(), "https://example.com");
+        let globals = IR::load_globals(&mut config, None).unwrap();
+        assert_eq!(globals["author"], Value::Text(String::from("derdilla")));
+        assert_eq!(globals["site-url"], Value::Text(String::from("https://example.org")));
+        assert!(matches!(globals["build-time"], Value::UnixTimestamp { .. }));
+
+        let mut config: WebsiteConf = serde_yml::from_str("url: example.com\nvariables:\n  author:\n").unwrap();
+        assert!(matches!(IR::load_globals(&mut config, None), Err(SourceFormatError::InvalidWebsiteVariable(_))));
+    }
+
+    #[test]
+    fn invalidates_pages_when_url_changes() {
+        let root = std::env::temp_dir().join("builder-url-fingerprint");
+        std::fs::create_dir_all(&root).unwrap();
+        let pages = FsTree::load(&root, &mut HashMap::new()).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+        let mut data = IR::new(SourceDir {
+            website_yml: String::from("url: example.com"),
+            templates: HashMap::new(),
+            components: HashMap::new(),
+            layout_css: String::new(),
+            style_css: String::new(),
+            pages,
+            static_files: Vec::new(),
+            git_commit: None,
+        }).unwrap();
+
+        let mut cache = BuildCache::default();
+        let dependencies = Dependencies::new();
+        dependencies.add(String::from("globals/canonical-url"));
+        dependencies.add(String::from("globals/page-path"));
+        cache.insert_page(String::from("pages/index.yml"), dependencies, &data, String::from("<p></p>"));
+        assert!(cache.page("pages/index.yml", &data).is_some());
+        data.config.url = String::from("example.org");
+        assert!(cache.page("pages/index.yml", &data).is_none());
     }
 }
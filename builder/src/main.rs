@@ -1,120 +1,184 @@
-use std::{env, fs};
-use std::path::PathBuf;
-use crate::fs_tree::{FsTreeLoadError, ParsedFsTreeParseError};
+use std::{env, fs, process};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use crate::build_cache::BuildCache;
+use crate::diagnostics::{Diagnostic, Format, Severity, Stage};
 
-use crate::ir::{FwHTMLError, IR, SourceFormatError};
-use crate::source_dir::{SourceDir, SourceLoadError};
-use crate::website_builder::Website;
+use crate::ir::IR;
+use crate::source_dir::SourceDir;
+use crate::website_builder::{Website, WriteError};
 
+mod build_cache;
+mod css;
+mod filters;
+mod diagnostics;
+mod minifier;
 mod source_dir;
 mod website_builder;
 mod ir;
 mod fs_tree;
+mod html;
 mod builder;
 mod sitemapper;
+mod server;
+mod validator;
+
+/// Exit code for invalid arguments.
+const USAGE_EXIT_CODE: i32 = 1;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
+    let mut format = Format::Human;
+    let mut output = None;
+    let mut args = Vec::new();
+    let mut raw_args = env::args().skip(1);
+    while let Some(arg) = raw_args.next() {
+        let (option, value) = match arg.split_once('=') {
+            Some((option, value)) => (option.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        match option.as_str() {
+            "--diagnostics-format" => {
+                let value = value.or_else(|| raw_args.next());
+                format = match value.as_deref().and_then(Format::parse) {
+                    None => {
+                        eprintln!("Unknown diagnostics format: {}", value.unwrap_or_default());
+                        print_usage();
+                        process::exit(USAGE_EXIT_CODE);
+                    },
+                    Some(format) => format,
+                };
+            },
+            "--diagnostics-output" => match value.or_else(|| raw_args.next()) {
+                None => {
+                    eprintln!("Missing diagnostics output file");
+                    print_usage();
+                    process::exit(USAGE_EXIT_CODE);
+                },
+                Some(file) => output = Some(PathBuf::from(file)),
+            },
+            _ => args.push(match value {
+                None => option,
+                Some(value) => format!("{option}={value}"),
+            }),
+        }
+    }
+    let output = output.as_deref();
+
+    if args.is_empty() {
         print_usage();
-        return;
+        process::exit(USAGE_EXIT_CODE);
     }
 
-    let command = &args[1];
+    let command = &args[0];
     let command = match command.as_str() {
         "assemble" => Command::Assemble,
         "pack" => Command::Pack,
         "compile" => Command::Compile,
+        "serve" => Command::Serve,
         _ => {
             eprintln!("Unknown command: {command}");
             print_usage();
-            return;
+            process::exit(USAGE_EXIT_CODE);
         },
     };
 
-    let working_dir = if args.len() >= 3 {
-        let path = PathBuf::from(&args[2]);
+    let working_dir = if args.len() >= 2 {
+        let path = PathBuf::from(&args[1]);
         if !path.is_dir() {
-            eprintln!("Directory doesn't exist: {}", &args[2]);
+            eprintln!("Directory doesn't exist: {}", &args[1]);
             print_usage();
-            return;
+            process::exit(USAGE_EXIT_CODE);
         }
         path
     } else {
         env::current_dir().expect("Can't access current working directory")
     };
 
-    println!("Reading data from '{}'...", &working_dir.to_str().unwrap());
-    // TODO: error building functions
+    if let Command::Serve = command {
+        let root = working_dir.clone();
+        // Kept across rebuilds instead of the cache file of the other commands
+        let cache = Mutex::new(BuildCache::in_memory());
+        return server::serve(root, server::DEFAULT_PORT, move || match build(&working_dir, &Command::Serve, &mut cache.lock().unwrap()) {
+            Err((_, diagnostics)) => Err(diagnostics.iter()
+                .map(|d| d.render(false))
+                .collect::<Vec<_>>()
+                .join("\n\n")),
+            Ok((website, warnings)) => {
+                diagnostics::report(&warnings, None, Format::Human, None);
+                Ok(website)
+            },
+        });
+    }
 
-    let source = match SourceDir::load(working_dir) {
-        Err(err) => return eprintln!("{}", err_source_load(err)),
-        Ok(source) => source,
-    };
-    let source = match IR::new(source) {
-        Err(err) => return eprintln!("{}", err_source_format(err)),
-        Ok(ir) => ir,
+    let mut cache = BuildCache::load(&working_dir);
+    let built = build(&working_dir, &command, &mut cache);
+    cache.save();
+    let (website, mut diagnostics) = match built {
+        Err((stage, diagnostics)) => {
+            diagnostics::report(&diagnostics, Some(stage), format, output);
+            process::exit(stage.exit_code());
+        },
+        Ok(built) => built,
     };
-    let website = Website::build(&source).unwrap();
-    if !website.validate() {
-        return;
-    }
     // TODO:
     // Fail on:
     // - Missing css classes or tags
     // Warn on:
     // - Unused files
     // - Missing index.html
-    if PathBuf::from("out").exists() {
-        fs::remove_dir_all("out").unwrap();
+    let out = PathBuf::from("out");
+    let written = match fs::remove_dir_all(&out) {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(WriteError::Io(out.clone(), err)),
+        _ => website.write(&out),
+    };
+    if let Err(err) = written {
+        diagnostics.push(diagnostics::write(err));
+        diagnostics::report(&diagnostics, Some(Stage::Write), format, output);
+        process::exit(Stage::Write.exit_code());
     }
-    website.write(&PathBuf::from("out"));
-}
-
-enum Command {
-    Assemble,
-    Pack,
-    Compile,
+    diagnostics::report(&diagnostics, None, format, output);
 }
 
-fn print_usage() {
-    eprintln!("Usage: builder <command> [path]");
-}
+/// Load the sources in [working_dir] and build the website in memory,
+/// reusing and updating the pages in [cache].
+///
+/// Warnings are returned alongside the website. On failure the failed stage
+/// and the problems it found are returned.
+fn build(working_dir: &Path, command: &Command, cache: &mut BuildCache) -> Result<(Website, Vec<Diagnostic>), (Stage, Vec<Diagnostic>)> {
+    eprintln!("Reading data from '{}'...", &working_dir.to_str().unwrap());
 
-fn err_source_load(err: SourceLoadError) -> String {
-    match err {
-        SourceLoadError::MissingFile(f) => format!("Couldn't read file: {f}"),
-        SourceLoadError::NoSuchDirectory(d) => format!("No such directory: {d}"),
-        SourceLoadError::BadFsTree(err) => format!("Can't load fs tree:\n\t{}", err_fs_tree_open(err))
+    let source = SourceDir::load(working_dir.to_path_buf(), &mut cache.created)
+        .map_err(|err| (Stage::Load, vec![diagnostics::source_load(err, working_dir)]))?;
+    let source = IR::new(source)
+        .map_err(|err| (Stage::Load, vec![diagnostics::source_format(err, working_dir)]))?;
+    let mut website = Website::build(&source, cache)
+        .map_err(|err| (Stage::Build, vec![diagnostics::website_build(err, working_dir)]))?;
+    if matches!(command, Command::Pack | Command::Compile) {
+        website.pack().map_err(|err| (Stage::Build, vec![diagnostics::pack(err, working_dir)]))?;
     }
-}
-
-fn err_source_format(err: SourceFormatError) -> String {
-    match err {
-        SourceFormatError::InvalidYaml(file, yaml) => format!("Invalid yml file: {file}"),
-        SourceFormatError::InvalidTemplateHTML(file, err) => format!("Invalid HTML in file: {file}\n\t{}", err_fw_html(err)),
-        SourceFormatError::BadWebsiteYaml => "website.yml in not in expected format".to_string(),
-        SourceFormatError::InvalidCSS(err) => panic!("TODO"),
-        SourceFormatError::InvalidFsTree(err) => err_fs_tree_parse(err),
+    if matches!(command, Command::Compile) {
+        website.compile();
     }
-}
-
-fn err_fs_tree_open(err: FsTreeLoadError) -> String {
-    match err {
-        FsTreeLoadError::CantReadPath(path, err) => format!("{}: {err}", path.to_str().unwrap_or("NON_UTF8_PATH")),
-        FsTreeLoadError::UnparsableFilename(path) => format!("Can't parse filename of: {}", path.to_str().unwrap_or("NON_UTF8_PATH")),
-        FsTreeLoadError::IsSymlink(path) => format!("Unexpected symlink at: {}", path.to_str().unwrap_or("NON_UTF8_PATH")),
+    let findings = validator::validate(&validator::Input {
+        website: &website,
+        source: &source,
+        root: working_dir,
+    });
+    if findings.iter().any(|finding| finding.severity == Severity::Error) {
+        return Err((Stage::Validation, findings));
     }
+    Ok((website, findings))
 }
 
-fn err_fs_tree_parse(err: ParsedFsTreeParseError) -> String {
-    match err {
-        ParsedFsTreeParseError::InvalidBuildProcedure(path, err) => {
-            todo!()
-        }
-    }
+enum Command {
+    Assemble,
+    Pack,
+    Compile,
+    /// Serve an assembled website on localhost and rebuild it on changes.
+    Serve,
 }
 
-fn err_fw_html(err: FwHTMLError) -> String {
-    todo!()
+fn print_usage() {
+    eprintln!("Usage: builder <command> [path] [--diagnostics-format human|json|sarif] [--diagnostics-output <file>]");
 }
@@ -0,0 +1,439 @@
+//! Size reduction of generated html, css and javascript.
+//!
+//! All minifiers are conservative: they only remove comments and whitespace
+//! where doing so can't change how the document is rendered or executed.
+
+/// Elements whose text content must be kept exactly as it is.
+const PRESERVING_ELEMENTS: [&str; 3] = ["pre", "code", "textarea"];
+
+/// Elements surrounding whitespace is insignificant for rendering.
+const BLOCK_ELEMENTS: [&str; 41] = [
+    "html", "head", "body", "meta", "link", "title", "style", "script", "base",
+    "main", "article", "section", "header", "footer", "nav", "aside", "address",
+    "div", "p", "h1", "h2", "h3", "h4", "h5", "h6", "hr", "pre", "blockquote",
+    "ul", "ol", "li", "dl", "dt", "dd", "table", "thead", "tbody", "tr", "td",
+    "th", "figure",
+];
+
+#[derive(Debug, PartialEq)]
+enum HtmlToken<'a> {
+    /// Includes the doctype and comments.
+    Markup(&'a str),
+    /// An opening or closing tag.
+    Tag {
+        raw: &'a str,
+        name: String,
+        closing: bool,
+    },
+    Text(&'a str),
+}
+
+/// Minify a html document including the content of `<style>` and `<script>`
+/// elements.
+///
+/// Comments get removed and whitespace gets collapsed, except for the content
+/// of elements in which whitespace is meaningful (like `<pre>`). Attribute
+/// values are never modified.
+pub fn html(html: &str) -> String {
+    let tokens = tokenize_html(html);
+    let mut out = String::with_capacity(html.len());
+    let mut preserving = 0;
+    let mut raw_text_element: Option<String> = None;
+    for (idx, token) in tokens.iter().enumerate() {
+        match token {
+            HtmlToken::Markup(raw) => {
+                if !raw.starts_with("<!--") {
+                    out += raw;
+                }
+            },
+            HtmlToken::Tag { raw, name, closing } => {
+                out += &minify_tag(raw);
+                if PRESERVING_ELEMENTS.contains(&name.as_str()) {
+                    if *closing {
+                        preserving -= 1;
+                    } else {
+                        preserving += 1;
+                    }
+                }
+                raw_text_element = if !closing && (name == "script" || name == "style") {
+                    Some(name.clone())
+                } else {
+                    None
+                };
+            },
+            HtmlToken::Text(text) => {
+                if let Some(element) = &raw_text_element {
+                    out += &match element.as_str() {
+                        "style" => css(text),
+                        _ => js(text),
+                    };
+                } else if preserving > 0 {
+                    out += text;
+                } else {
+                    let block_before = idx.checked_sub(1)
+                        .and_then(|i| tokens.get(i))
+                        .is_none_or(is_block_boundary);
+                    let block_after = tokens.get(idx + 1).is_none_or(is_block_boundary);
+                    out += &collapse_whitespace(text, block_before, block_after);
+                }
+            },
+        }
+    }
+    out
+}
+
+/// Whether whitespace next to this token is insignificant.
+fn is_block_boundary(token: &HtmlToken) -> bool {
+    match token {
+        HtmlToken::Markup(_) => true,
+        HtmlToken::Tag { name, .. } => BLOCK_ELEMENTS.contains(&name.as_str()),
+        HtmlToken::Text(_) => false,
+    }
+}
+
+fn collapse_whitespace(text: &str, trim_start: bool, trim_end: bool) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut in_whitespace = false;
+    for c in text.chars() {
+        if c.is_ascii_whitespace() {
+            in_whitespace = true;
+        } else {
+            if in_whitespace && !(trim_start && out.is_empty()) {
+                out.push(' ');
+            }
+            in_whitespace = false;
+            out.push(c);
+        }
+    }
+    if in_whitespace && !trim_end && !(trim_start && out.is_empty()) {
+        out.push(' ');
+    }
+    out
+}
+
+/// Collapse whitespace between attributes without touching quoted values.
+fn minify_tag(tag: &str) -> String {
+    let mut out = String::with_capacity(tag.len());
+    let mut quote = None;
+    let mut in_whitespace = false;
+    for c in tag.chars() {
+        match quote {
+            Some(q) => {
+                out.push(c);
+                if c == q {
+                    quote = None;
+                }
+            },
+            None if c.is_ascii_whitespace() => in_whitespace = true,
+            None => {
+                if in_whitespace && c != '>' {
+                    out.push(' ');
+                }
+                in_whitespace = false;
+                if c == '"' || c == '\'' {
+                    quote = Some(c);
+                }
+                out.push(c);
+            },
+        }
+    }
+    out
+}
+
+fn tokenize_html(html: &str) -> Vec<HtmlToken<'_>> {
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    let mut text_start = 0;
+    while let Some(offset) = html[pos..].find('<') {
+        let start = pos + offset;
+        let rest = &html[start..];
+        let markup_end = if rest.starts_with("<!--") {
+            rest.find("-->").map(|e| e + 3)
+        } else if rest.starts_with("<!") {
+            rest.find('>').map(|e| e + 1)
+        } else if rest[1..].starts_with(|c: char| c.is_ascii_alphabetic() || c == '/') {
+            tag_end(rest)
+        } else {
+            pos = start + 1;
+            continue;
+        };
+        let end = match markup_end {
+            None => break,
+            Some(len) => start + len,
+        };
+
+        if text_start < start {
+            tokens.push(HtmlToken::Text(&html[text_start..start]));
+        }
+        let raw = &html[start..end];
+        if raw.starts_with("<!") {
+            tokens.push(HtmlToken::Markup(raw));
+            pos = end;
+        } else {
+            let closing = raw.starts_with("</");
+            let name: String = raw.trim_start_matches(['<', '/'])
+                .chars()
+                .take_while(|c| c.is_ascii_alphanumeric())
+                .collect::<String>()
+                .to_lowercase();
+            let is_raw_text = !closing && (name == "script" || name == "style");
+            tokens.push(HtmlToken::Tag { raw, name: name.clone(), closing });
+            pos = end;
+            if is_raw_text {
+                // Content of raw text elements is not html.
+                let content_end = html[pos..].to_ascii_lowercase()
+                    .find(&format!("</{name}"))
+                    .map_or(html.len(), |e| pos + e);
+                if pos < content_end {
+                    tokens.push(HtmlToken::Text(&html[pos..content_end]));
+                }
+                pos = content_end;
+            }
+        }
+        text_start = pos;
+    }
+    if text_start < html.len() {
+        tokens.push(HtmlToken::Text(&html[text_start..]));
+    }
+    tokens
+}
+
+/// Length of the tag at the start of [html] respecting quoted attributes.
+fn tag_end(html: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in html.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {},
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '>' => return Some(i + 1),
+            None => {},
+        }
+    }
+    None
+}
+
+/// Minify a stylesheet by removing comments and unnecessary whitespace.
+pub fn css(css: &str) -> String {
+    const PUNCTUATION: [char; 8] = ['{', '}', ';', ':', ',', '>', '~', '!'];
+
+    let mut out = String::with_capacity(css.len());
+    let mut chars = css.chars().peekable();
+    let mut pending_space = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut last = ' ';
+                for c in chars.by_ref() {
+                    if last == '*' && c == '/' {
+                        break;
+                    }
+                    last = c;
+                }
+                pending_space = true;
+            },
+            '"' | '\'' => {
+                if pending_space && !out.ends_with(PUNCTUATION) && !out.is_empty() {
+                    out.push(' ');
+                }
+                pending_space = false;
+                out.push(c);
+                let mut escaped = false;
+                for s in chars.by_ref() {
+                    out.push(s);
+                    if !escaped && s == c {
+                        break;
+                    }
+                    escaped = !escaped && s == '\\';
+                }
+            },
+            c if c.is_whitespace() => pending_space = true,
+            c => {
+                // A space before ':' is kept, as it is a descendant combinator in selectors.
+                let drop_space = out.is_empty()
+                    || out.ends_with(PUNCTUATION)
+                    || (PUNCTUATION.contains(&c) && c != ':');
+                if pending_space && !drop_space {
+                    out.push(' ');
+                }
+                pending_space = false;
+                if c == '}' && out.ends_with(';') {
+                    out.pop();
+                }
+                out.push(c);
+            },
+        }
+    }
+    out
+}
+
+/// Minify javascript by removing comments and collapsing whitespace.
+///
+/// Line breaks are kept to not interfere with automatic semicolon insertion.
+pub fn js(js: &str) -> String {
+    let mut out = String::with_capacity(js.len());
+    let chars: Vec<char> = js.chars().collect();
+    let mut i = 0;
+    let mut pending_space = false;
+    let mut pending_newline = false;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if c == '/' && next == Some('/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            continue;
+        }
+        if c == '/' && next == Some('*') {
+            i += 2;
+            while i < chars.len() && !(chars[i - 1] == '*' && chars[i] == '/') {
+                i += 1;
+            }
+            i += 1;
+            pending_space = true;
+            continue;
+        }
+        if c == '\n' || c == '\r' {
+            pending_newline = true;
+            i += 1;
+            continue;
+        }
+        if c.is_whitespace() {
+            pending_space = true;
+            i += 1;
+            continue;
+        }
+
+        if pending_newline && !out.is_empty() {
+            out.push('\n');
+        } else if pending_space && out.chars().last().is_some_and(|last| needs_js_space(last, c)) {
+            out.push(' ');
+        }
+        pending_space = false;
+        pending_newline = false;
+
+        let starts_regex = c == '/' && (out.trim_end().chars().last()
+            .is_none_or(|last| "(,=:[!&|?{};+-*%<>~^".contains(last))
+            || ends_with_keyword(&out));
+        if c == '"' || c == '\'' || c == '`' || starts_regex {
+            // Copy literals verbatim.
+            out.push(c);
+            i += 1;
+            let mut escaped = false;
+            let mut in_class = false;
+            while i < chars.len() {
+                let s = chars[i];
+                out.push(s);
+                i += 1;
+                if escaped {
+                    escaped = false;
+                } else if s == '\\' {
+                    escaped = true;
+                } else if starts_regex && s == '[' {
+                    in_class = true;
+                } else if starts_regex && s == ']' {
+                    in_class = false;
+                } else if s == c && !in_class {
+                    break;
+                }
+            }
+            continue;
+        }
+        out.push(c);
+        i += 1;
+    }
+    out
+}
+
+/// Keywords after which a `/` starts a regex instead of being a division.
+const KEYWORDS_BEFORE_EXPRESSION: [&str; 11] = ["return", "typeof", "case", "in", "of", "void", "delete", "throw", "new", "do", "else"];
+
+/// Whether the last token of [js] is one of [KEYWORDS_BEFORE_EXPRESSION] and
+/// not a property, e.g. `a.return`.
+fn ends_with_keyword(js: &str) -> bool {
+    let js = js.trim_end();
+    let word = &js[js.trim_end_matches(is_js_word_char).len()..];
+    let before = js[..js.len() - word.len()].trim_end();
+    KEYWORDS_BEFORE_EXPRESSION.contains(&word) && !before.ends_with('.')
+}
+
+/// Whether whitespace between [last] and [next] separates tokens that would
+/// otherwise merge, e.g. `a + +b` into `a++b` or `a / /b/` into a comment.
+fn needs_js_space(last: char, next: char) -> bool {
+    (is_js_word_char(last) && is_js_word_char(next))
+        || (last == '+' && next == '+')
+        || (last == '-' && next == '-')
+        || (next == '/' && "/+-*%<>=&|^!~?:".contains(last))
+}
+
+fn is_js_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$' || !c.is_ascii()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::minifier;
+
+    #[test]
+    fn minifies_html() {
+        let html = minifier::html(r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <!-- comment -->
+    <meta name="description"   content="a  b">
+    <style>
+        main + footer {
+            width: 100vw;
+        }
+    </style>
+</head>
+<body>
+    <header>
+        <h2>Title</h2>
+        <time datetime="2024-08-23T19:31:18+00:00">2024-08-23</time>
+    </header>
+    <p>Some   <b>bold</b> <i>text</i>.
+    </p>
+</body>
+</html>"#);
+        assert_eq!(html, concat!(
+            r#"<!DOCTYPE html><html lang="en"><head><meta name="description" content="a  b">"#,
+            r#"<style>main + footer{width:100vw}</style></head><body><header><h2>Title</h2>"#,
+            r#"<time datetime="2024-08-23T19:31:18+00:00">2024-08-23</time></header>"#,
+            r#"<p>Some <b>bold</b> <i>text</i>.</p></body></html>"#,
+        ));
+    }
+
+    #[test]
+    fn keeps_preformatted_text() {
+        let html = "<p>\n    <code>a  <b>b</b>\n  c</code>\n</p>\n<pre><code>fn main() {\n    x  y\n}\n</code></pre>";
+        assert_eq!(minifier::html(html), "<p><code>a  <b>b</b>\n  c</code></p><pre><code>fn main() {\n    x  y\n}\n</code></pre>");
+    }
+
+    #[test]
+    fn minifies_css() {
+        assert_eq!(minifier::css(r#"
+/* comment */
+header > :is(h1, h2) + time {
+    margin-left: 0.2em;
+    padding: max(3vw, calc((100% - 2px) / 2));
+}
+.a :hover::after { content: " } "; }
+"#), r#"header>:is(h1,h2) + time{margin-left:0.2em;padding:max(3vw,calc((100% - 2px) / 2))}.a :hover::after{content:" } "}"#);
+    }
+
+    #[test]
+    fn minifies_js() {
+        assert_eq!(minifier::js(r#"
+// comment
+const a = "  // not a comment  ";
+let b = a.replace(/ +/g, ' ') /* inline */ + `
+  template`;
+if (a) { b = 1 }
+"#), "const a=\"  // not a comment  \";\nlet b=a.replace(/ +/g,' ')+`\n  template`;\nif(a){b=1}");
+        assert_eq!(minifier::js("function f(x) {\n  return /a  b/.test(x) || typeof /x  y/ == x.in / 2;\n}"), "function f(x){\nreturn/a  b/.test(x)||typeof/x  y/==x.in/2;\n}");
+        assert_eq!(minifier::js("x = a + +b - -c + ++d;\ny = a / /re/.exec(s).length;"), "x=a+ +b- -c+ ++d;\ny=a/ /re/.exec(s).length;");
+    }
+}
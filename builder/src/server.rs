@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::website_builder::Website;
+
+pub const DEFAULT_PORT: u16 = 8000;
+
+/// Files and directories relative to the source root that trigger a rebuild.
+const WATCHED_PATHS: [&str; 7] = ["pages", "components", "templates", "static", "layout.css", "style.css", "website.yml"];
+
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+const LIVE_RELOAD_PATH: &str = "/__livereload";
+
+const LIVE_RELOAD_SCRIPT: &str = r#"<script>new EventSource("/__livereload").onmessage = () => location.reload();</script>"#;
+
+/// Latest build result shared between the watcher and all connections.
+struct SiteState {
+    /// Either the website files or a description of why building failed.
+    build: Result<HashMap<PathBuf, Vec<u8>>, String>,
+    /// Increased on every rebuild.
+    generation: u64,
+}
+
+type SharedState = Arc<(Mutex<SiteState>, Condvar)>;
+
+/// Serve the website built by [build] on localhost and rebuild it whenever
+/// source files in [root] change.
+///
+/// Open pages are reloaded through a server sent event after every rebuild.
+/// This function only returns when the server can't be started.
+pub fn serve<F>(root: PathBuf, port: u16, build: F)
+where
+    F: Fn() -> Result<Website, String> + Send + 'static,
+{
+    let listener = match TcpListener::bind(("127.0.0.1", port)) {
+        Err(err) => return eprintln!("Can't listen on port {port}: {err}"),
+        Ok(listener) => listener,
+    };
+
+    let state: SharedState = Arc::new((Mutex::new(SiteState {
+        build: run_build(&build),
+        generation: 0,
+    }), Condvar::new()));
+    println!("Serving on http://localhost:{port}/");
+
+    let watcher_state = state.clone();
+    thread::spawn(move || watch(&root, &watcher_state, build));
+
+    for stream in listener.incoming() {
+        match stream {
+            Err(err) => eprintln!("Connection failed: {err}"),
+            Ok(stream) => {
+                let state = state.clone();
+                thread::spawn(move || {
+                    if let Err(err) = handle_connection(stream, &state) {
+                        eprintln!("Error while responding: {err}");
+                    }
+                });
+            },
+        }
+    }
+}
+
+/// Build the website and turn failures (including panics) into messages.
+fn run_build<F>(build: &F) -> Result<HashMap<PathBuf, Vec<u8>>, String>
+where
+    F: Fn() -> Result<Website, String>,
+{
+    match panic::catch_unwind(panic::AssertUnwindSafe(build)) {
+        Ok(Ok(website)) => Ok(website.pages.into_iter().collect()),
+        Ok(Err(err)) => {
+            eprintln!("{err}");
+            Err(err)
+        },
+        Err(_) => Err(String::from("The builder panicked, see the console output for details.")),
+    }
+}
+
+fn watch<F>(root: &Path, state: &SharedState, build: F)
+where
+    F: Fn() -> Result<Website, String>,
+{
+    let mut last_fingerprint = fingerprint(root);
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        let fingerprint = fingerprint(root);
+        if fingerprint == last_fingerprint {
+            continue;
+        }
+        last_fingerprint = fingerprint;
+
+        println!("Change detected, rebuilding...");
+        let result = run_build(&build);
+        let (lock, reload) = &**state;
+        let mut site = lock.lock().unwrap();
+        site.build = result;
+        site.generation += 1;
+        reload.notify_all();
+    }
+}
+
+/// Hash of the paths, sizes and modification times of all watched files.
+fn fingerprint(root: &Path) -> u64 {
+    fn visit(path: &Path, hasher: &mut DefaultHasher) {
+        if let Ok(meta) = fs::metadata(path) {
+            path.hash(hasher);
+            meta.len().hash(hasher);
+            if let Ok(modified) = meta.modified() {
+                modified.duration_since(UNIX_EPOCH).unwrap_or_default().hash(hasher);
+            }
+            if meta.is_dir() {
+                if let Ok(entries) = fs::read_dir(path) {
+                    let mut entries: Vec<PathBuf> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+                    entries.sort();
+                    for entry in entries {
+                        visit(&entry, hasher);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut hasher = DefaultHasher::new();
+    for path in WATCHED_PATHS {
+        visit(&root.join(path), &mut hasher);
+    }
+    hasher.finish()
+}
+
+fn handle_connection(mut stream: TcpStream, state: &SharedState) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip headers
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method, target),
+        _ => return respond(&mut stream, false, "400 Bad Request", "text/plain", b"Bad Request"),
+    };
+    if method != "GET" && method != "HEAD" {
+        return respond(&mut stream, false, "405 Method Not Allowed", "text/plain", b"Method Not Allowed");
+    }
+    let head = method == "HEAD";
+    let path = percent_decode(target.split(['?', '#']).next().unwrap_or("/"));
+
+    if path == LIVE_RELOAD_PATH && !head {
+        return stream_reloads(stream, state);
+    }
+
+    let (lock, _) = &**state;
+    let site = lock.lock().unwrap();
+    let files = match &site.build {
+        Err(err) => {
+            let page = error_page(err);
+            drop(site);
+            return respond(&mut stream, head, "500 Internal Server Error", "text/html; charset=utf-8", page.as_bytes());
+        },
+        Ok(files) => files,
+    };
+
+    match resolve(files, &path) {
+        Some((file, content)) => {
+            let content_type = content_type(&file);
+            let content = with_live_reload(&file, content);
+            drop(site);
+            respond(&mut stream, head, "200 OK", content_type, &content)
+        },
+        None => {
+            let not_found = files.get(&PathBuf::from("404.html"))
+                .map(|page| with_live_reload(Path::new("404.html"), page))
+                .unwrap_or_else(|| b"Not Found".to_vec());
+            drop(site);
+            respond(&mut stream, head, "404 Not Found", "text/html; charset=utf-8", &not_found)
+        },
+    }
+}
+
+/// Find the file a request path refers to the same way a static file server
+/// would.
+fn resolve<'a>(files: &'a HashMap<PathBuf, Vec<u8>>, path: &str) -> Option<(PathBuf, &'a Vec<u8>)> {
+    candidates(path).into_iter()
+        .find_map(|candidate| files.get(&candidate).map(|content| (candidate, content)))
+}
+
+/// Files a request [path] can refer to, in the order they are looked up.
+pub fn candidates(path: &str) -> Vec<PathBuf> {
+    let path = path.trim_start_matches('/');
+    let candidates = if path.is_empty() || path.ends_with('/') {
+        vec![format!("{path}index.html")]
+    } else {
+        vec![path.to_string(), format!("{path}/index.html"), format!("{path}.html")]
+    };
+    candidates.into_iter().map(PathBuf::from).collect()
+}
+
+/// Keep the connection open and send an event after every rebuild.
+fn stream_reloads(mut stream: TcpStream, state: &SharedState) -> std::io::Result<()> {
+    stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n")?;
+    let (lock, reload) = &**state;
+    let mut site = lock.lock().unwrap();
+    let mut generation = site.generation;
+    loop {
+        let (guard, timeout) = reload.wait_timeout(site, Duration::from_secs(15)).unwrap();
+        site = guard;
+        let event: &[u8] = if site.generation != generation {
+            generation = site.generation;
+            b"data: reload\n\n"
+        } else if timeout.timed_out() {
+            // Detects closed connections.
+            b": keep-alive\n\n"
+        } else {
+            continue;
+        };
+        if stream.write_all(event).is_err() {
+            // The page was closed or reloaded.
+            return Ok(());
+        }
+    }
+}
+
+/// Write a response with [body], which is left out for [head] requests.
+fn respond(stream: &mut impl Write, head: bool, status: &str, content_type: &str, body: &[u8]) -> std::io::Result<()> {
+    write!(stream, "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n", body.len())?;
+    if !head {
+        stream.write_all(body)?;
+    }
+    stream.flush()
+}
+
+fn with_live_reload(file: &Path, content: &[u8]) -> Vec<u8> {
+    if file.extension().is_none_or(|e| e != "html") {
+        return content.to_vec();
+    }
+    let html = String::from_utf8_lossy(content);
+    let html = match html.rfind("</body>") {
+        Some(idx) => format!("{}{LIVE_RELOAD_SCRIPT}{}", &html[..idx], &html[idx..]),
+        None => format!("{html}{LIVE_RELOAD_SCRIPT}"),
+    };
+    html.into_bytes()
+}
+
+fn error_page(err: &str) -> String {
+    let err = err.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;");
+    format!("<!DOCTYPE html>\n<html lang=\"en\">\n<head><meta charset=\"UTF-8\"><title>Build failed</title></head>\n<body>\n<h1>Build failed</h1>\n<pre>{err}</pre>\n{LIVE_RELOAD_SCRIPT}\n</body>\n</html>")
+}
+
+fn content_type(file: &Path) -> &'static str {
+    match file.extension().and_then(|e| e.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("webm") => "video/webm",
+        Some("woff2") => "font/woff2",
+        Some("txt") => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+pub fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            },
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    use crate::server::{percent_decode, resolve, respond};
+
+    #[test]
+    fn resolves_like_static_hosting() {
+        let files = HashMap::from([
+            (PathBuf::from("index.html"), b"index".to_vec()),
+            (PathBuf::from("blog/index.html"), b"blog".to_vec()),
+            (PathBuf::from("blog/post.html"), b"post".to_vec()),
+            (PathBuf::from("layout.css"), b"css".to_vec()),
+        ]);
+        let content = |path| resolve(&files, path).map(|(_, c)| c.clone());
+        assert_eq!(content("/"), Some(b"index".to_vec()));
+        assert_eq!(content("/blog"), Some(b"blog".to_vec()));
+        assert_eq!(content("/blog/"), Some(b"blog".to_vec()));
+        assert_eq!(content("/blog/post"), Some(b"post".to_vec()));
+        assert_eq!(content("/layout.css"), Some(b"css".to_vec()));
+        assert_eq!(content("/missing"), None);
+        assert_eq!(percent_decode("/a%20b%2"), "/a b%2");
+    }
+
+    #[test]
+    fn leaves_out_body_for_head_requests() {
+        let mut response = Vec::new();
+        respond(&mut response, true, "200 OK", "text/plain", b"body").unwrap();
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n") && response.contains("Content-Length: 4\r\n"));
+        assert!(response.ends_with("\r\n\r\n"));
+
+        let mut response = Vec::new();
+        respond(&mut response, false, "200 OK", "text/plain", b"body").unwrap();
+        assert!(response.ends_with(b"\r\n\r\nbody"));
+    }
+}
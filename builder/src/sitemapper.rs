@@ -74,12 +74,12 @@ impl ModificationTimestampResolver {
     pub fn new() -> Self {
         let txt = fs::read_to_string("page-modifications");
         if txt.is_err() {
-            const YELLOW: &'static str = "\x1b[33m";
-            const CLEAR: &'static str = "\x1b[0m";
+            const YELLOW: &str = "\x1b[33m";
+            const CLEAR: &str = "\x1b[0m";
             eprintln!("{YELLOW}WARNING{CLEAR}: No existing page modifications file detected. Creating a new one...")
         }
         ModificationTimestampResolver {
-            old_txt: txt.unwrap_or(String::new()),
+            old_txt: txt.unwrap_or_default(),
             queried: Vec::new(),
         }
     }
@@ -101,7 +101,7 @@ impl ModificationTimestampResolver {
             .next();
 
         let timestamp = timestamp.unwrap_or(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64);
-        self.queried.push((hash.to_string(), timestamp.clone()));
+        self.queried.push((hash.to_string(), timestamp));
 
         DateTime::from_timestamp_millis(timestamp).unwrap()
     }
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use std::{fs, io};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 
 use crate::fs_tree::{FsTree, FsTreeLoadError};
 
@@ -12,14 +13,16 @@ pub struct SourceDir {
     /// Contents of the website.yml file.
     pub website_yml: String,
 
-    /// Template file names and content.
+    /// Template file names relative to the templates dir and content.
     ///
-    /// Example entry: `"base-page.html", "<!DOCTYPE html>..."`
+    /// Example entry: `"base-page.html", "<!DOCTYPE html>..."`,
+    /// `"blog/post.html", "<!DOCTYPE html>..."`
     pub templates: HashMap<String, String>,
 
     /// Component ids and file content.
     ///
-    /// Example entry: `"footer", "<footer>...</footer>"`
+    /// Example entry: `"footer", "<footer>...</footer>"`,
+    /// `"blog/entry", "<article>...</article>"`
     pub components: HashMap<String, String>,
 
     pub layout_css: String,
@@ -28,20 +31,27 @@ pub struct SourceDir {
 
     pub pages: FsTree,
 
-    pub static_files: Vec<(PathBuf, Vec<u8>)>
+    pub static_files: Vec<(PathBuf, Vec<u8>)>,
+
+    /// Hash of the commit checked out in the source root, if it is in a git
+    /// repository.
+    pub git_commit: Option<String>,
 }
 
 impl SourceDir {
 
-    pub fn load(root: PathBuf) -> Result<Self, SourceLoadError> {
+    /// Load all sources in [root].
+    ///
+    /// Git timestamps are looked up in and added to [created_cache].
+    pub fn load(root: PathBuf, created_cache: &mut HashMap<PathBuf, u64>) -> Result<Self, SourceLoadError> {
         let website_yml = Self::read_website(&root)?;
         let templates = Self::read_templates(&root)?;
         let components = Self::read_components(&root)?;
         let layout = Self::read_layout(&root)?;
         let style = Self::read_style(&root)?;
-        let pages = Self::load_fs_tree(&root)?;
+        let pages = Self::load_fs_tree(&root, created_cache)?;
         let mut static_files = Vec::new();
-        if let Err(_) =  Self::collect_files(root.join("static"), &root.join("static"), &mut static_files) {
+        if Self::collect_files(root.join("static"), &root.join("static"), &mut static_files).is_err() {
             return Err(SourceLoadError::NoSuchDirectory(String::from("static")));
         }
 
@@ -53,73 +63,84 @@ impl SourceDir {
             style_css: style,
             pages,
             static_files,
+            git_commit: git_commit(&root),
         })
     }
 
-    fn read_website(root: &PathBuf) -> Result<String, SourceLoadError> {
+    fn read_website(root: &Path) -> Result<String, SourceLoadError> {
         match fs::read_to_string(root.join("website.yml")) {
-            Err(_) => return Err(SourceLoadError::MissingFile(String::from("website.yml"))),
+            Err(_) => Err(SourceLoadError::MissingFile(String::from("website.yml"))),
             Ok(txt) => Ok(txt),
         }
     }
 
-    fn read_layout(root: &PathBuf) -> Result<String, SourceLoadError> {
+    fn read_layout(root: &Path) -> Result<String, SourceLoadError> {
         match fs::read_to_string(root.join("layout.css")) {
-            Err(_) => return Err(SourceLoadError::MissingFile(String::from("layout.css"))),
+            Err(_) => Err(SourceLoadError::MissingFile(String::from("layout.css"))),
             Ok(txt) => Ok(txt),
         }
     }
 
-    fn read_style(root: &PathBuf) -> Result<String, SourceLoadError> {
+    fn read_style(root: &Path) -> Result<String, SourceLoadError> {
         match fs::read_to_string(root.join("style.css")) {
-            Err(_) => return Err(SourceLoadError::MissingFile(String::from("style.css"))),
+            Err(_) => Err(SourceLoadError::MissingFile(String::from("style.css"))),
             Ok(txt) => Ok(txt),
         }
     }
 
-    fn read_templates(root: &PathBuf) -> Result<HashMap<String, String>, SourceLoadError> {
-        let templates = root.join("templates");
-        Self::read_dir(&templates)
+    fn read_templates(root: &Path) -> Result<HashMap<String, String>, SourceLoadError> {
+        let templates = Self::read_dir(&root.join("templates"))?;
+        if let Some((name, other)) = colliding_name(templates.keys(), str::to_string) {
+            return Err(SourceLoadError::CollidingNames(format!("templates/{name}"), format!("templates/{other}")));
+        }
+        Ok(templates)
     }
 
-    fn read_components(root: &PathBuf) -> Result<HashMap<String, String>, SourceLoadError> {
+    fn read_components(root: &Path) -> Result<HashMap<String, String>, SourceLoadError> {
         let components = root.join("components");
-        let components = Self::read_dir(&components);
-        components.and_then(|components| Ok(components.iter()
-            .map(|(k, v)| {
-                let k = k.strip_suffix(".html");
-                (k, v)
-            })
-            .filter(|(k, _)| k.is_some())
-            .map(|(k, v)| (k.unwrap().to_string(), v.to_string()))
-            .collect::<HashMap<String, String>>())
-        )
+        let components = Self::read_dir(&components)?.into_iter()
+            .filter_map(|(k, v)| Some((k.strip_suffix(".html")?.to_string(), v)))
+            .collect::<HashMap<String, String>>();
+        if let Some((name, other)) = colliding_name(components.keys(), str::to_string) {
+            return Err(SourceLoadError::CollidingNames(format!("components/{name}.html"), format!("components/{other}.html")));
+        }
+        Ok(components)
     }
 
-    fn load_fs_tree(root: &PathBuf) -> Result<FsTree, SourceLoadError> {
-        match FsTree::load(&root.join("pages")) {
+    fn load_fs_tree(root: &Path, created_cache: &mut HashMap<PathBuf, u64>) -> Result<FsTree, SourceLoadError> {
+        match FsTree::load(&root.join("pages"), created_cache) {
             Err(err) => Err(SourceLoadError::BadFsTree(err)),
             Ok(fs_tree) => Ok(fs_tree),
         }
     }
 
-    /// Attempt to load files at the top level of [dir] into memory.
+    /// Attempt to load all files in [dir] and its subdirectories into memory.
     ///
-    /// The resulting name is the file name as key and the content as value.
-    fn read_dir(dir: &PathBuf) -> Result<HashMap<String, String>, SourceLoadError> {
+    /// The resulting name is the path relative to [dir] with `/` separators as
+    /// key and the content as value.
+    fn read_dir(dir: &Path) -> Result<HashMap<String, String>, SourceLoadError> {
+        let mut loaded_files = HashMap::new();
+        Self::read_dir_into(dir, "", &mut loaded_files)?;
+        Ok(loaded_files)
+    }
+
+    fn read_dir_into(dir: &Path, prefix: &str, loaded_files: &mut HashMap<String, String>) -> Result<(), SourceLoadError> {
         let files = match dir.read_dir() {
             Ok(d) => d,
             Err(_) => return Err(SourceLoadError::NoSuchDirectory(dir.to_str().unwrap().to_string())),
         };
-        let mut loaded_files = HashMap::new();
         for t in files {
             if let Ok(e) = t {
                 let path = &e.path();
+                let file_name = format!("{prefix}{}", e.file_name().to_str().unwrap());
+                if path.is_dir() {
+                    Self::read_dir_into(path, &format!("{file_name}/"), loaded_files)?;
+                    continue;
+                }
                 if !path.is_file() {
                     continue;
                 }
-                if let Ok(content) = fs::read_to_string(&path) {
-                    let file_name = e.file_name().to_str().unwrap().to_string();
+                if let Ok(content) = fs::read_to_string(path) {
                     loaded_files.insert(file_name, content);
                 } else {
                     return Err(SourceLoadError::MissingFile(path.to_str().unwrap().to_string()));
@@ -128,7 +149,7 @@ impl SourceDir {
                 return Err(SourceLoadError::NoSuchDirectory(dir.to_str().unwrap().to_string()));
             }
         };
-        Ok(loaded_files)
+        Ok(())
     }
 
     /// Recursively read all files relative to a root dir
@@ -138,7 +159,7 @@ impl SourceDir {
             let path = entry.path();
 
             if path.is_dir() {
-                Self::collect_files(path, &prefix_dir, files)?;
+                Self::collect_files(path, prefix_dir, files)?;
             } else if path.is_file() {
                 let file_content = fs::read(&path)?;
                 let path = path.canonicalize().unwrap();
@@ -153,9 +174,60 @@ impl SourceDir {
     }
 }
 
+/// Hash of the commit checked out in [root].
+fn git_commit(root: &Path) -> Option<String> {
+    let out = Command::new("git")
+        .arg("rev-parse")
+        .arg("HEAD")
+        .current_dir(root)
+        .output().ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    String::from_utf8(out.stdout).ok()
+        .map(|commit| commit.trim().to_string())
+}
+
+/// Find two names whose [output] only differs in case, e.g. `blog/Entry` and
+/// `blog/entry`, which are the same file on case-insensitive file systems, or
+/// the pages `foo.yml` and `foo.md` which both build `foo.html`.
+pub fn colliding_name<'a>(names: impl Iterator<Item = &'a String>, output: impl Fn(&str) -> String) -> Option<(String, String)> {
+    let mut names = names.collect::<Vec<_>>();
+    names.sort();
+    let mut seen: HashMap<String, &String> = HashMap::new();
+    for name in names {
+        if let Some(other) = seen.insert(output(name).to_lowercase(), name) {
+            return Some((other.clone(), name.clone()));
+        }
+    }
+    None
+}
+
 #[derive(Debug)]
 pub enum SourceLoadError {
     MissingFile(String),
     NoSuchDirectory(String),
-    BadFsTree(FsTreeLoadError)
+    BadFsTree(FsTreeLoadError),
+    /// File, file whose name only differs in case
+    CollidingNames(String, String),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::source_dir::colliding_name;
+
+    #[test]
+    fn detects_colliding_names() {
+        let names = [String::from("blog/entry"), String::from("blog"), String::from("blog/list")];
+        assert_eq!(colliding_name(names.iter(), str::to_string), None);
+
+        let names = [String::from("blog/entry"), String::from("Blog/Entry"), String::from("footer")];
+        assert_eq!(colliding_name(names.iter(), str::to_string), Some((String::from("Blog/Entry"), String::from("blog/entry"))));
+
+        let html = |page: &str| Path::new(page).with_extension("html").to_string_lossy().to_string();
+        let names = [String::from("blog/a.yml"), String::from("blog/a.md"), String::from("a.yml")];
+        assert_eq!(colliding_name(names.iter(), html), Some((String::from("blog/a.md"), String::from("blog/a.yml"))));
+    }
 }
\ No newline at end of file
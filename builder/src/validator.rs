@@ -0,0 +1,649 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+
+use itertools::Itertools;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
+use serde::Deserialize;
+
+use crate::css::{self, Stylesheet};
+use crate::diagnostics::{self, Diagnostic, Location, Severity};
+use crate::fs_tree::ParsedFsEntry;
+use crate::html;
+use crate::ir::IR;
+use crate::server;
+use crate::website_builder::Website;
+
+static UNRESOLVED_VARIABLE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{\{ [^\s|]*(?:\s*\|[^}]*)? }}").unwrap());
+
+/// Matches urls that don't point into the website, like `https://…` or
+/// `mailto:…`.
+static EXTERNAL_URL: Lazy<Regex> = Lazy::new(|| Regex::new(r"^([a-zA-Z][a-zA-Z0-9+.-]*:|//)").unwrap());
+
+/// Maximum number of characters of generated html quoted in diagnostics.
+const MAX_EXCERPT_LENGTH: usize = 80;
+
+/// Attributes containing urls to files of the website.
+const REFERENCE_ATTRIBUTES: [&str; 4] = ["href", "src", "srcset", "poster"];
+
+/// How findings of a rule are treated.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    /// The rule doesn't run.
+    Off,
+    /// Findings get reported but don't fail the build.
+    Warn,
+    /// Findings fail the build.
+    Error,
+}
+
+/// Everything a rule can inspect.
+pub struct Input<'a> {
+    pub website: &'a Website,
+    pub source: &'a IR,
+    /// Directory the website is built from.
+    pub root: &'a Path,
+}
+
+/// A problem found by a [Rule].
+pub struct Finding {
+    /// Generated page the problem occurs in, used for suppressions.
+    pub page: Option<PathBuf>,
+    /// Description of the problem, the severity gets overridden by the
+    /// configured [Level].
+    pub diagnostic: Diagnostic,
+}
+
+/// A named check over the built website and its sources.
+pub trait Rule {
+    /// Name used to configure the rule in `website.yml` and to suppress it in
+    /// pages.
+    fn name(&self) -> &'static str;
+
+    /// Level used when `website.yml` doesn't configure one.
+    fn default_level(&self) -> Level;
+
+    fn check(&self, input: &Input) -> Vec<Finding>;
+}
+
+/// All rules that can be configured.
+fn rules() -> Vec<Box<dyn Rule>> {
+    vec![
+        Box::new(UnresolvedVariable),
+        Box::new(MissingIndex),
+        Box::new(BrokenReference),
+        Box::new(DuplicateId),
+        Box::new(InvalidHtml),
+        Box::new(UndefinedClass),
+        Box::new(CollidingClass),
+        Box::new(UnusedSelector),
+    ]
+}
+
+/// Run all enabled rules on the website.
+///
+/// Findings in pages that suppress the rule are dropped. The build should
+/// only fail when an error-level diagnostic is returned.
+pub fn validate(input: &Input) -> Vec<Diagnostic> {
+    eprintln!("validating...");
+    let rules = rules();
+    let mut diagnostics = unknown_rules(input, &rules);
+    let levels = &input.source.config.rules;
+    for rule in rules {
+        let severity = match levels.get(rule.name()).copied().unwrap_or(rule.default_level()) {
+            Level::Off => continue,
+            Level::Warn => Severity::Warning,
+            Level::Error => Severity::Error,
+        };
+        for finding in rule.check(input) {
+            let suppressed = finding.page.as_ref()
+                .and_then(|page| suppressions(input, page))
+                .is_some_and(|suppressed| suppressed.iter().any(|s| s == rule.name()));
+            if suppressed {
+                continue;
+            }
+            diagnostics.push(Diagnostic {
+                severity,
+                rule: Some(rule.name()),
+                ..finding.diagnostic
+            });
+        }
+    }
+    diagnostics
+}
+
+/// Rules suppressed by the build procedure of a generated [page].
+fn suppressions<'a>(input: &'a Input, page: &Path) -> Option<&'a Vec<String>> {
+    let source = input.website.sources.get(page)?;
+    match &input.source.pages.get_tree(source.to_str()?)?.content {
+        ParsedFsEntry::BuildProcedure(procedure) => Some(&procedure.suppressed),
+        _ => None,
+    }
+}
+
+/// Report rule names in the configuration or in suppressions that don't
+/// exist.
+fn unknown_rules(input: &Input, rules: &[Box<dyn Rule>]) -> Vec<Diagnostic> {
+    let is_unknown = |name: &String| !rules.iter().any(|rule| rule.name() == name);
+    let mut diagnostics: Vec<Diagnostic> = input.source.config.rules.keys()
+        .filter(|name| is_unknown(name))
+        .map(|name| Diagnostic::error(format!("Unknown rule `{name}`"))
+            .in_file("website.yml")
+            .locate(input.root, &["rules", name]))
+        .collect();
+    for (path, procedure) in input.source.pages.procedures() {
+        for name in procedure.suppressed.iter().filter(|name| is_unknown(name)) {
+            diagnostics.push(Diagnostic::error(format!("Unknown rule `{name}`"))
+                .in_file(&path)
+                .locate(input.root, &["suppress", name]));
+        }
+    }
+    diagnostics
+}
+
+/// Point [diagnostic] at the source file that contains [needle] and
+/// produced the generated [page].
+///
+/// The page's own source is preferred over the templates, components and
+/// files it was built from. When no source contains [needle], the page's
+/// source is used without location.
+fn located(input: &Input, page: &Path, needle: &str, diagnostic: Diagnostic) -> Diagnostic {
+    let source = input.website.sources.get(page);
+    let inputs = input.website.inputs.get(page).into_iter()
+        .flatten()
+        .filter_map(|dependency| match dependency.strip_prefix("components/") {
+            Some(component) => Some(PathBuf::from(format!("components/{component}.html"))),
+            None => Some(PathBuf::from(dependency)).filter(|path| path.extension().is_some()),
+        });
+    let origin = source.cloned().into_iter()
+        .chain(inputs)
+        .find(|file| std::fs::read_to_string(input.root.join(file))
+            .is_ok_and(|content| !needle.is_empty() && content.contains(needle)));
+    match (origin, source) {
+        (Some(origin), _) => diagnostic.in_file(origin).locate(input.root, &[needle]),
+        (None, Some(source)) => diagnostic.in_file(source),
+        (None, None) => diagnostic,
+    }
+}
+
+/// Position of the [nth] occurrence of [needle] in a generated [page] for
+/// the context of a diagnostic.
+fn generated_context(page: &Path, html: &str, needle: &str, nth: usize) -> String {
+    let offset = html.match_indices(needle).nth(nth).map(|(offset, _)| offset);
+    match offset.and_then(|offset| Location::from_offset(html, offset, needle.len())) {
+        Some(location) => format!("in generated file `{}` at {}:{}", page.display(), location.line, location.column),
+        None => format!("in generated file `{}`", page.display()),
+    }
+}
+
+/// `{{ name }}` placeholders that are left in generated html.
+struct UnresolvedVariable;
+
+impl Rule for UnresolvedVariable {
+    fn name(&self) -> &'static str {
+        "unresolved-variable"
+    }
+
+    fn default_level(&self) -> Level {
+        Level::Error
+    }
+
+    fn check(&self, input: &Input) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        for (page, content) in &input.website.pages {
+            if page.extension().is_none_or(|e| e != "html") {
+                continue;
+            }
+            let html = String::from_utf8_lossy(content);
+            for placeholder in UNRESOLVED_VARIABLE.find_iter(&html) {
+                let name = placeholder.as_str().trim_start_matches("{{").trim_end_matches("}}")
+                    .split('|').next().unwrap_or_default()
+                    .trim();
+                let context = match Location::from_offset(&html, placeholder.start(), placeholder.len()) {
+                    Some(generated) => format!("in generated file `{}` at {}:{}", page.display(), generated.line, generated.column),
+                    None => format!("in generated file `{}`", page.display()),
+                };
+                let diagnostic = Diagnostic::error(format!("Variable `{name}` is never resolved"))
+                    .with_context(vec![context]);
+                let diagnostic = located(input, page, placeholder.as_str(), diagnostic);
+                findings.push(Finding { page: Some(page.clone()), diagnostic });
+            }
+        }
+        findings
+    }
+}
+
+/// Directories containing html files that can't be opened by their path
+/// because they have no `index.html`.
+struct MissingIndex;
+
+impl Rule for MissingIndex {
+    fn name(&self) -> &'static str {
+        "missing-index"
+    }
+
+    fn default_level(&self) -> Level {
+        Level::Warn
+    }
+
+    fn check(&self, input: &Input) -> Vec<Finding> {
+        let mut directories: HashMap<PathBuf, bool> = HashMap::new();
+        for (page, _) in &input.website.pages {
+            if page.extension().is_none_or(|e| e != "html") {
+                continue;
+            }
+            for dir in page.ancestors().skip(1) {
+                let has_index = page.parent() == Some(dir) && page.file_name().is_some_and(|n| n == "index.html");
+                *directories.entry(dir.to_path_buf()).or_default() |= has_index;
+            }
+        }
+        let mut missing: Vec<PathBuf> = directories.into_iter()
+            .filter(|(_, has_index)| !has_index)
+            .map(|(dir, _)| dir)
+            .collect();
+        missing.sort();
+        missing.into_iter()
+            .map(|dir| Finding {
+                page: None,
+                diagnostic: Diagnostic::error(format!("Directory `/{}` has no index.html", dir.display())),
+            })
+            .collect()
+    }
+}
+
+/// Parsed html pages of the website by their path.
+fn documents(website: &Website) -> Vec<(&Path, &str, Html)> {
+    website.pages.iter()
+        .filter(|(page, _)| page.extension().is_some_and(|e| e == "html"))
+        .filter_map(|(page, content)| {
+            let html = std::str::from_utf8(content).ok()?;
+            Some((page.as_path(), html, Html::parse_document(html)))
+        })
+        .collect()
+}
+
+/// Describe an [element] by its tag and one [attribute].
+fn describe(element: &ElementRef, attribute: &str) -> String {
+    let value = element.value().attr(attribute).unwrap_or_default();
+    format!("<{} {attribute}=\"{value}\">", element.value().name())
+}
+
+/// Urls and fragments in `href`, `src`, `srcset` and `poster` attributes
+/// that don't exist in the generated website.
+struct BrokenReference;
+
+impl Rule for BrokenReference {
+    fn name(&self) -> &'static str {
+        "broken-reference"
+    }
+
+    fn default_level(&self) -> Level {
+        Level::Error
+    }
+
+    fn check(&self, input: &Input) -> Vec<Finding> {
+        let files: HashSet<&Path> = input.website.pages.iter()
+            .map(|(page, _)| page.as_path())
+            .collect();
+        let documents = documents(input.website);
+        let anchors: HashMap<&Path, HashSet<&str>> = documents.iter()
+            .map(|(page, _, document)| {
+                let anchors = document.root_element().descendants()
+                    .filter_map(ElementRef::wrap)
+                    .flat_map(|e| [e.value().id(), e.value().attr("name").filter(|_| e.value().name() == "a")])
+                    .flatten()
+                    .collect();
+                (*page, anchors)
+            })
+            .collect();
+
+        let mut findings = Vec::new();
+        for (page, html, document) in &documents {
+            let mut occurrences: HashMap<String, usize> = HashMap::new();
+            for element in document.root_element().descendants().filter_map(ElementRef::wrap) {
+                for attribute in REFERENCE_ATTRIBUTES {
+                    let Some(value) = element.value().attr(attribute) else {
+                        continue;
+                    };
+                    let needle = format!("{attribute}=\"{value}\"");
+                    let occurrence = occurrences.entry(needle.clone()).or_default();
+                    let nth = *occurrence;
+                    *occurrence += 1;
+                    let urls: Vec<&str> = if attribute == "srcset" {
+                        value.split(',').filter_map(|candidate| candidate.split_whitespace().next()).collect()
+                    } else {
+                        vec![value.trim()]
+                    };
+                    for url in urls {
+                        let message = match resolve_reference(page, url, &files) {
+                            Reference::Valid => continue,
+                            Reference::MissingFile => format!("`{url}` doesn't exist"),
+                            Reference::Fragment(target, fragment) => {
+                                let exists = anchors.get(target.as_path())
+                                    .is_none_or(|anchors| anchors.contains(fragment.as_str()));
+                                if exists {
+                                    continue;
+                                }
+                                format!("`{url}` links to `#{fragment}` which doesn't exist in `{}`", target.display())
+                            },
+                        };
+                        let element = describe(&element, attribute);
+                        let diagnostic = Diagnostic::error(message)
+                            .with_context(vec![
+                                generated_context(page, html, &needle, nth),
+                                format!("in `{element}`"),
+                            ]);
+                        findings.push(Finding {
+                            page: Some(page.to_path_buf()),
+                            diagnostic: located(input, page, value, diagnostic),
+                        });
+                    }
+                }
+            }
+        }
+        findings
+    }
+}
+
+/// Result of resolving a url inside the website.
+#[derive(Debug, PartialEq)]
+enum Reference {
+    /// The url points outside the website or to an existing file.
+    Valid,
+    MissingFile,
+    /// The url points to a fragment of an existing page that still needs to
+    /// be checked. Page, fragment
+    Fragment(PathBuf, String),
+}
+
+/// Resolve a [url] found in the generated [page] against the website's
+/// [files] the same way static hosting does.
+fn resolve_reference(page: &Path, url: &str, files: &HashSet<&Path>) -> Reference {
+    if url.is_empty() || EXTERNAL_URL.is_match(url) || UNRESOLVED_VARIABLE.is_match(url) {
+        return Reference::Valid;
+    }
+    let (path, fragment) = url.split_once('#').unwrap_or((url, ""));
+    let path = path.split('?').next().unwrap_or_default();
+
+    let target = if path.is_empty() {
+        page.to_path_buf()
+    } else {
+        let path = server::percent_decode(path);
+        let joined = match path.strip_prefix('/') {
+            Some(path) => PathBuf::from(path),
+            None => page.parent().unwrap_or(Path::new("")).join(&path),
+        };
+        let mut normalized = PathBuf::new();
+        for component in joined.components() {
+            match component {
+                Component::Normal(part) => normalized.push(part),
+                // Going above the root fails like with static hosting.
+                Component::ParentDir if !normalized.pop() => return Reference::MissingFile,
+                _ => {},
+            }
+        }
+        let mut normalized = normalized.to_string_lossy().to_string();
+        if path.ends_with('/') && !normalized.is_empty() {
+            normalized.push('/');
+        }
+        match server::candidates(&normalized).into_iter().find(|candidate| files.contains(candidate.as_path())) {
+            None => return Reference::MissingFile,
+            Some(target) => target,
+        }
+    };
+
+    if fragment.is_empty() || fragment.eq_ignore_ascii_case("top") || target.extension().is_none_or(|e| e != "html") {
+        Reference::Valid
+    } else {
+        Reference::Fragment(target, server::percent_decode(fragment))
+    }
+}
+
+/// Ids that are used by more than one element of a page.
+struct DuplicateId;
+
+impl Rule for DuplicateId {
+    fn name(&self) -> &'static str {
+        "duplicate-id"
+    }
+
+    fn default_level(&self) -> Level {
+        Level::Error
+    }
+
+    fn check(&self, input: &Input) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        for (page, html, document) in documents(input.website) {
+            let elements = document.root_element().descendants()
+                .filter_map(ElementRef::wrap)
+                .filter_map(|e| e.value().id().map(|id| (id, e)))
+                .into_group_map();
+            for (id, elements) in elements.into_iter().sorted_by_key(|(id, _)| *id) {
+                if elements.len() < 2 {
+                    continue;
+                }
+                let diagnostic = Diagnostic::error(format!("Id `{id}` is used by {} elements", elements.len()))
+                    .with_context(vec![
+                        generated_context(page, html, &format!("id=\"{id}\""), 1),
+                        format!("in {}", elements.iter().map(|e| format!("`{}`", describe(e, "id"))).join(", ")),
+                    ]);
+                findings.push(Finding {
+                    page: Some(page.to_path_buf()),
+                    diagnostic: located(input, page, &format!("id=\"{id}\""), diagnostic),
+                });
+            }
+        }
+        findings
+    }
+}
+
+/// Errors the html parser has to recover from in generated pages, like
+/// unclosed or misnested tags.
+struct InvalidHtml;
+
+impl Rule for InvalidHtml {
+    fn name(&self) -> &'static str {
+        "invalid-html"
+    }
+
+    fn default_level(&self) -> Level {
+        Level::Error
+    }
+
+    fn check(&self, input: &Input) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        for (page, content) in &input.website.pages {
+            if page.extension().is_none_or(|e| e != "html") {
+                continue;
+            }
+            let html = String::from_utf8_lossy(content);
+            let (_, errors) = html::parse_document(&html);
+            for error in errors {
+                let line = html.lines().nth(error.line - 1).unwrap_or_default().trim();
+                let excerpt = match line.char_indices().nth(MAX_EXCERPT_LENGTH) {
+                    Some((end, _)) => format!("{}…", &line[..end]),
+                    None => line.to_string(),
+                };
+                let diagnostic = Diagnostic::error(format!("Invalid html: {}", error.message))
+                    .with_context(vec![format!("in generated file `{}` at line {}: `{excerpt}`", page.display(), error.line)]);
+                let diagnostic = located(input, page, line, diagnostic);
+                findings.push(Finding { page: Some(page.clone()), diagnostic });
+            }
+        }
+        findings
+    }
+}
+
+/// Classes that are added by the Markdown renderer to fenced code blocks and
+/// only serve as hooks for syntax highlighters.
+const CODE_LANGUAGE_PREFIX: &str = "language-";
+
+/// Point [diagnostic] at the first occurrence of [needle] in the stylesheet
+/// [file] that isn't commented out.
+fn located_in_css(input: &Input, file: &str, needle: &str, diagnostic: Diagnostic) -> Diagnostic {
+    let diagnostic = diagnostic.in_file(file);
+    let offset = std::fs::read_to_string(input.root.join(file)).ok()
+        .and_then(|content| diagnostics::find_word(&css::blank_comments(&content), needle));
+    match offset {
+        Some(offset) => diagnostic.at_offset(input.root, offset, needle.chars().count()),
+        None => diagnostic,
+    }
+}
+
+/// Classes defined by the selectors of a [stylesheet].
+fn defined_classes(stylesheet: &Stylesheet) -> HashSet<String> {
+    stylesheet.selectors().into_iter()
+        .flat_map(|selector| selector.classes())
+        .collect()
+}
+
+/// Classes defined by the inline `<style>` elements of a [document].
+///
+/// Stylesheets that can't be parsed define no classes.
+fn inline_classes(document: &Html) -> HashSet<String> {
+    let style = Selector::parse("style").unwrap();
+    document.select(&style)
+        .filter_map(|element| Stylesheet::parse(&element.text().collect::<String>()).ok())
+        .flat_map(|stylesheet| defined_classes(&stylesheet))
+        .collect()
+}
+
+/// Classes used in pages that neither the stylesheets nor an inline
+/// `<style>` of the page define.
+struct UndefinedClass;
+
+impl Rule for UndefinedClass {
+    fn name(&self) -> &'static str {
+        "undefined-class"
+    }
+
+    fn default_level(&self) -> Level {
+        Level::Error
+    }
+
+    fn check(&self, input: &Input) -> Vec<Finding> {
+        let defined: HashSet<String> = input.source.stylesheets.iter()
+            .flat_map(|(_, stylesheet)| defined_classes(stylesheet))
+            .collect();
+        let mut findings = Vec::new();
+        for (page, html, document) in documents(input.website) {
+            let inline = inline_classes(&document);
+            let used: HashSet<&str> = document.root_element().descendants()
+                .filter_map(ElementRef::wrap)
+                .flat_map(|e| e.value().classes())
+                .collect();
+            for class in used.into_iter().sorted() {
+                if defined.contains(class) || inline.contains(class) || class.starts_with(CODE_LANGUAGE_PREFIX) {
+                    continue;
+                }
+                let diagnostic = Diagnostic::error(format!("Class `{class}` is not defined in any stylesheet"))
+                    .with_context(vec![generated_context(page, html, class, 0)]);
+                findings.push(Finding {
+                    page: Some(page.to_path_buf()),
+                    diagnostic: located(input, page, class, diagnostic),
+                });
+            }
+        }
+        findings
+    }
+}
+
+/// Classes that are defined in more than one of the stylesheets.
+struct CollidingClass;
+
+impl Rule for CollidingClass {
+    fn name(&self) -> &'static str {
+        "colliding-class"
+    }
+
+    fn default_level(&self) -> Level {
+        Level::Error
+    }
+
+    fn check(&self, input: &Input) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        for (i, (file, stylesheet)) in input.source.stylesheets.iter().enumerate() {
+            let defined = defined_classes(stylesheet);
+            for (other_file, other) in &input.source.stylesheets[..i] {
+                for class in defined.intersection(&defined_classes(other)).sorted() {
+                    let diagnostic = Diagnostic::error(format!("Class `{class}` is also defined in {other_file}"));
+                    let diagnostic = located_in_css(input, file, &format!(".{class}"), diagnostic);
+                    findings.push(Finding { page: None, diagnostic });
+                }
+            }
+        }
+        findings
+    }
+}
+
+/// Selectors of the stylesheets that match no element of any page.
+///
+/// Interaction states and pseudo-elements are ignored, so `a:hover` is used
+/// by every page containing a link.
+struct UnusedSelector;
+
+impl Rule for UnusedSelector {
+    fn name(&self) -> &'static str {
+        "unused-selector"
+    }
+
+    fn default_level(&self) -> Level {
+        Level::Warn
+    }
+
+    fn check(&self, input: &Input) -> Vec<Finding> {
+        let documents = documents(input.website);
+        let mut findings = Vec::new();
+        for (file, stylesheet) in &input.source.stylesheets {
+            for selector in stylesheet.selectors() {
+                let Ok(parsed) = Selector::parse(&selector.static_selector()) else {
+                    continue;
+                };
+                if documents.iter().any(|(_, _, document)| document.select(&parsed).next().is_some()) {
+                    continue;
+                }
+                let diagnostic = Diagnostic::error(format!("Selector `{}` matches no element of any page", selector.source));
+                let diagnostic = located_in_css(input, file, &selector.source, diagnostic);
+                findings.push(Finding { page: None, diagnostic });
+            }
+        }
+        findings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::path::{Path, PathBuf};
+
+    use crate::validator::{resolve_reference, Reference, UNRESOLVED_VARIABLE};
+
+    #[test]
+    fn validation_regex_works() {
+        assert!(UNRESOLVED_VARIABLE.is_match("{{ test }}"));
+        assert!(UNRESOLVED_VARIABLE.is_match("{{ test/csom }}"));
+        assert!(!UNRESOLVED_VARIABLE.is_match("{{ test }"));
+        assert!(UNRESOLVED_VARIABLE.is_match("{{ test | truncate(20) }}"));
+    }
+
+    #[test]
+    fn resolves_references_like_static_hosting() {
+        let files: HashSet<&Path> = ["index.html", "blog/index.html", "blog/post.html", "assets/a.svg"].into_iter()
+            .map(Path::new)
+            .collect();
+        let page = Path::new("blog/index.html");
+        assert_eq!(resolve_reference(page, "post.html", &files), Reference::Valid);
+        assert_eq!(resolve_reference(page, "post", &files), Reference::Valid);
+        assert_eq!(resolve_reference(page, "/assets/a.svg?v=1", &files), Reference::Valid);
+        assert_eq!(resolve_reference(page, "../assets/a.svg", &files), Reference::Valid);
+        assert_eq!(resolve_reference(page, "assets/a.svg", &files), Reference::MissingFile);
+        assert_eq!(resolve_reference(page, "../../index.html", &files), Reference::MissingFile);
+        assert_eq!(resolve_reference(page, "https://example.com/missing", &files), Reference::Valid);
+        assert_eq!(resolve_reference(page, "mailto:a@b.c", &files), Reference::Valid);
+        assert_eq!(resolve_reference(page, "/#top", &files), Reference::Valid);
+        assert_eq!(resolve_reference(page, "/#intro", &files), Reference::Fragment(PathBuf::from("index.html"), String::from("intro")));
+        assert_eq!(resolve_reference(page, "#intro", &files), Reference::Fragment(PathBuf::from("blog/index.html"), String::from("intro")));
+    }
+}
@@ -1,54 +1,94 @@
-use std::fs;
+use std::collections::HashMap;
+use std::{fs, io};
 use std::io::Write;
-use std::path::PathBuf;
-use itertools::Itertools;
-use lewp_css::domain::at_rules::font_face::FontDisplay::fallback;
-use regex::{Regex, Replacer};
+use std::path::{Path, PathBuf};
+use regex::Regex;
 
-use crate::builder::BuildProcedureBuildError;
-use crate::fs_tree::ParsedFsEntry;
+use crate::build_cache::{BuildCache, Dependencies};
+use crate::builder::{BuildProcedure, BuildProcedureBuildError, ValueCache};
+use crate::css::{Stylesheet, StylesheetParseError};
+use crate::ir;
 use crate::ir::IR;
+use crate::minifier;
 use crate::sitemapper::SiteMapBuilder;
 
+/// Stylesheets that are inlined into pages by [Website::pack].
+const PACKED_STYLESHEETS: [&str; 2] = ["layout.css", "style.css"];
+
 pub struct Website {
     pub pages: Vec<(PathBuf, Vec<u8>)>,
+    /// Build procedures and static files relative to the source root by the
+    /// pages built from them.
+    pub sources: HashMap<PathBuf, PathBuf>,
+    /// [Dependencies] entries of every built page.
+    pub inputs: HashMap<PathBuf, Vec<String>>,
 }
 
 impl Website {
-    pub fn build(source: &IR) -> Result<Self, BuildProcedureBuildError> {
+    /// Build all pages and add static files.
+    ///
+    /// Pages whose inputs didn't change since they were added to [cache] are
+    /// not built again.
+    pub fn build(source: &IR, cache: &mut BuildCache) -> Result<Self, WebsiteBuildError> {
         let mut build_pages = Vec::new();
 
-        println!("Copying static assets:");
+        let mut sources = HashMap::new();
+        let mut inputs = HashMap::new();
+        eprintln!("Copying static assets:");
+        for (path, _) in &source.static_assets {
+            sources.insert(path.clone(), PathBuf::from("static").join(path));
+        }
         build_pages.append(&mut source.static_assets.clone());
         build_pages.push((PathBuf::from("layout.css"), source.layout_css.as_bytes().to_vec()));
         build_pages.push((PathBuf::from("style.css"),  source.style_css.as_bytes().to_vec()));
 
-        println!("Building pages:");
-        let build_scripts = source.pages.filter("yml");
+        eprintln!("Building pages:");
+        let build_scripts = source.pages.procedures().into_iter()
+            .filter(|(path, _)| !path.file_name().and_then(|name| name.to_str()).is_some_and(BuildProcedure::is_partial))
+            .collect::<Vec<_>>();
         let total = build_scripts.len();
         let mut page_count = 0;
-        let mut sitemap = SiteMapBuilder::new((&source.config.url).clone());
+        let mut sitemap = SiteMapBuilder::new(source.config.url.clone());
+        let mut built_scripts = Vec::new();
+        let values = ValueCache::new();
         for (mut path, build_script) in build_scripts {
-            if let ParsedFsEntry::BuildProcedure(build_script) = build_script {
-                path.set_extension("html");
-                let path = PathBuf::from(path.strip_prefix("pages/").unwrap_or(&path));
-                page_count += 1;
-                println!("> {} ({} / {})", &path.to_str().unwrap(), &page_count, &total);
-                let html = build_script.execute(&source)?;
-
-                let html = html.as_bytes().to_vec();
-                sitemap.add(path.to_str().unwrap().to_string(), &html);
-                build_pages.push((path, html));
-            }
+            let script_path = path.to_str().unwrap().to_string();
+            path.set_extension("html");
+            let path = PathBuf::from(path.strip_prefix("pages/").unwrap_or(&path));
+            page_count += 1;
+            let html = match cache.page(&script_path, source) {
+                Some(html) => {
+                    eprintln!("> {} ({} / {}, unchanged)", &path.to_str().unwrap(), &page_count, &total);
+                    html
+                },
+                None => {
+                    eprintln!("> {} ({} / {})", &path.to_str().unwrap(), &page_count, &total);
+                    let dependencies = Dependencies::new();
+                    dependencies.add(script_path.clone());
+                    let html = build_script.execute(source, path.to_str().unwrap(), &values, &dependencies)
+                        .map_err(|err| WebsiteBuildError::PageBuildFailed(PathBuf::from(&script_path), err))?;
+                    cache.insert_page(script_path.clone(), dependencies, source, html.clone());
+                    html
+                },
+            };
+            sources.insert(path.clone(), PathBuf::from(&script_path));
+            inputs.insert(path.clone(), cache.dependencies(&script_path));
+            built_scripts.push(script_path);
+
+            let html = html.as_bytes().to_vec();
+            sitemap.add(path.to_str().unwrap().to_string(), &html);
+            build_pages.push((path, html));
         }
 
-        println!("Creating aliases:");
+        cache.retain_pages(&built_scripts);
+
+        eprintln!("Creating aliases:");
         let mut aliases = Vec::new();
         for (path, content) in &build_pages {
             let path = path.to_str().unwrap();
             if path.ends_with(".html") && !path.ends_with("index.html") {
-                let name = path.split('/').last().unwrap().strip_suffix(".html").unwrap();
-                println!("> {}", &path);
+                let name = path.split('/').next_back().unwrap().strip_suffix(".html").unwrap();
+                eprintln!("> {}", &path);
 
                 let cannonical = format!("<link rel=\"canonical\" href=\"/{path}\" />");
                 let content = String::from_utf8(content.clone()).expect("html files are utf8")
@@ -56,80 +96,170 @@ impl Website {
                     .as_bytes().to_vec();
                 
                 let idx_path = path.replace(format!("{name}.html").as_str(), format!("{name}/index.html").as_str());
-                println!("  - {}", &idx_path);
+                eprintln!("  - {}", &idx_path);
+                if let Some(source) = sources.get(&PathBuf::from(path)) {
+                    sources.insert(PathBuf::from(&idx_path), source.clone());
+                }
+                if let Some(page_inputs) = inputs.get(&PathBuf::from(path)) {
+                    inputs.insert(PathBuf::from(&idx_path), page_inputs.clone());
+                }
                 aliases.push((PathBuf::from(idx_path), content));
             }
         }
-        println!("> {} aliases created", &aliases.len());
+        eprintln!("> {} aliases created", &aliases.len());
 
         build_pages.append(&mut aliases);
 
-        println!("Building sitemap:");
+        eprintln!("Building sitemap:");
         build_pages.push((PathBuf::from("sitemap.xml"), sitemap.build().as_bytes().to_vec()));
 
-        Ok(Website { pages: build_pages })
+        Ok(Website { pages: build_pages, sources, inputs })
     }
 
-    /// Validates generated files for unresolved variables and components.
+    /// Inline the stylesheets into every html page and remove rules a page
+    /// doesn't use.
     ///
-    /// When errors are found they get printed and false gets returned.
-    pub fn validate(&self) -> bool {
-        println!("validating...");
-        const RED: &'static str = "\x1b[31m";
-        const CLEAR: &'static str = "\x1b[0m";
-
-        let mut valid = true;
-        let regex = Regex::new(r"\{\{ [^\s]* }}").unwrap();
-        for (path, content) in self.pages.clone() {
-            if path.extension().is_some_and(|e| e.to_str().unwrap() == "html") {
-                if regex.is_match(&String::from_utf8(content).unwrap()) {
-                    eprintln!("{RED}ERROR{CLEAR}: Unresolved variable in generated file {}", path.display());
-                    valid = false;
+    /// Stylesheets that are inlined are no longer part of the website.
+    pub fn pack(&mut self) -> Result<(), PackError> {
+        eprintln!("Packing css:");
+        let mut stylesheets = Vec::new();
+        for name in PACKED_STYLESHEETS {
+            let path = PathBuf::from(name);
+            if let Some(idx) = self.pages.iter().position(|(p, _)| p == &path) {
+                let (_, css) = self.pages.remove(idx);
+                let css = String::from_utf8(css).map_err(|_| PackError::NonUtf8(path.clone()))?;
+                let css = Stylesheet::parse(&css).map_err(|err| PackError::InvalidCss(name.to_string(), err))?;
+                stylesheets.push((name, css));
+            }
+        }
+
+        let link_regex = Regex::new(r#"<link [^>]*href="/?([^"]*\.css)"[^>]*>\s*"#).unwrap();
+        for (path, content) in self.pages.iter_mut() {
+            if path.extension().is_none_or(|e| e != "html") {
+                continue;
+            }
+            let html = String::from_utf8(content.clone()).map_err(|_| PackError::NonUtf8(path.clone()))?;
+            let used = ir::used_names(&html);
+
+            let mut inlined = Stylesheet { rules: Vec::new() };
+            let mut insert_at = None;
+            let mut packed = String::new();
+            let mut last_end = 0;
+            for link in link_regex.captures_iter(&html) {
+                let whole = link.get(0).unwrap();
+                let sheet = stylesheets.iter().find(|(name, _)| *name == &link[1]);
+                if let Some((_, sheet)) = sheet {
+                    packed += &html[last_end..whole.start()];
+                    insert_at.get_or_insert(packed.len());
+                    last_end = whole.end();
+                    inlined.append(sheet.tree_shaken(&used));
                 }
             }
+            packed += &html[last_end..];
+
+            if let Some(insert_at) = insert_at {
+                inlined.hoist_imports();
+                let before = content.len();
+                packed.insert_str(insert_at, &format!("<style>
+{}
+</style>
+    ", inlined.output()));
+                eprintln!("> {} ({} -> {} bytes)", path.display(), before, packed.len());
+                *content = packed.into_bytes();
+            }
         }
-        valid
+        Ok(())
     }
 
-    pub fn write(&self, out: &PathBuf) -> bool {
-        if out.is_file() {
-            panic!("Can't write to file")
-        } else if !out.exists() {
-            if fs::create_dir_all(&out).is_err() {
-                panic!("Can't create out dir")
-            }
-        } else if out.is_dir() {  } else { panic!("????") };
+    /// Minify all html, css and javascript files of the website.
+    pub fn compile(&mut self) {
+        eprintln!("Minifying:");
+        let mut total_before = 0;
+        let mut total_after = 0;
+        for (path, content) in self.pages.iter_mut() {
+            let minify = match path.extension().and_then(|e| e.to_str()) {
+                Some("html") => minifier::html,
+                Some("css") => minifier::css,
+                Some("js") => minifier::js,
+                _ => continue,
+            };
+            let Ok(text) = std::str::from_utf8(content) else {
+                continue;
+            };
+            let minified = minify(text).into_bytes();
+            let before = content.len();
+            let after = minified.len();
+            eprintln!("> {} ({before} -> {after} bytes, -{:.1}%)", path.display(), percent_saved(before, after));
+            total_before += before;
+            total_after += after;
+            *content = minified;
+        }
+        eprintln!("> total ({total_before} -> {total_after} bytes, -{:.1}%)", percent_saved(total_before, total_after));
+    }
 
-        if out.read_dir().unwrap().count() > 0 {
-            panic!("Out dir is not empty")
+    /// Write all files into the empty or missing directory [out].
+    pub fn write(&self, out: &Path) -> Result<(), WriteError> {
+        if out.exists() && !out.is_dir() {
+            return Err(WriteError::NotADirectory(out.to_path_buf()));
+        }
+        if let Err(err) = fs::create_dir_all(out) {
+            return Err(WriteError::Io(out.to_path_buf(), err));
+        }
+        match out.read_dir() {
+            Err(err) => return Err(WriteError::Io(out.to_path_buf(), err)),
+            Ok(mut entries) => if entries.next().is_some() {
+                return Err(WriteError::NotEmpty(out.to_path_buf()));
+            },
         }
 
         for (sub_path, content) in &self.pages {
-            Self::write_to_pathbuf(&out.join(sub_path), content).unwrap();
+            let path = out.join(sub_path);
+            if let Err(err) = Self::write_to_pathbuf(&path, content) {
+                return Err(WriteError::Io(path, err));
+            }
         }
-        true
+        Ok(())
     }
 
-    fn write_to_pathbuf(path: &PathBuf, content: &Vec<u8>) -> std::io::Result<()> { // TODO: evaluate and remove?
+    fn write_to_pathbuf(path: &Path, content: &[u8]) -> io::Result<()> { // TODO: evaluate and remove?
         if let Some(parent) = path.parent() {
             fs::create_dir_all(parent)?;
         }
 
-        println!("writing {:?}", &path);
+        eprintln!("writing {:?}", &path);
         let mut file = fs::File::create(path)?;
         file.write_all(content)?;
         Ok(())
     }
 }
 
-mod test {
-    use regex::Regex;
-
-    #[test]
-    fn validation_regex_works() {
-        let regex = Regex::new(r"\{\{ [^\s]* }}").unwrap();
-        assert!(regex.is_match("{{ test }}"));
-        assert!(regex.is_match("{{ test/csom }}"));
-        assert!(!regex.is_match("{{ test }"));
+fn percent_saved(before: usize, after: usize) -> f64 {
+    if before == 0 {
+        0.0
+    } else {
+        (before - after) as f64 / before as f64 * 100.0
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug)]
+pub enum WebsiteBuildError {
+    /// Path of the build procedure, err
+    PageBuildFailed(PathBuf, BuildProcedureBuildError),
+}
+
+#[derive(Debug)]
+pub enum PackError {
+    NonUtf8(PathBuf),
+    /// Stylesheet file name, err
+    InvalidCss(String, StylesheetParseError),
+}
+
+#[derive(Debug)]
+pub enum WriteError {
+    /// Output path exists but is no directory.
+    NotADirectory(PathBuf),
+    /// Output directory contains files.
+    NotEmpty(PathBuf),
+    /// Path, err
+    Io(PathBuf, io::Error),
+}